@@ -0,0 +1,42 @@
+use std::io::Cursor;
+
+use adv2020_4::{generate_passports, CharReader, IntoPassports, IntoTokens};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+// Builds the same input for a given count on every run. Half of the passports are valid and the
+// rest are missing a field or have an invalid value so that all three statuses are exercised
+fn generate(count: usize) -> String {
+    let mut input = String::new();
+    for passport in generate_passports(count, 0.5, 2020) {
+        input.push_str(&format!("{}\n\n", passport));
+    }
+    input
+}
+
+fn count_valid(chars: CharReader<Cursor<&[u8]>>) -> usize {
+    chars
+        .into_tokens()
+        .into_passports()
+        .filter(|p| p.as_ref().map(|p| p.is_valid()).unwrap_or(false))
+        .count()
+}
+
+fn bench_readers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_and_validate");
+    for &size in SIZES.iter() {
+        let input = generate(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("forward", size), &input, |b, input| {
+            b.iter(|| count_valid(CharReader::new(Cursor::new(input.as_bytes()))))
+        });
+        group.bench_with_input(BenchmarkId::new("reversed", size), &input, |b, input| {
+            b.iter(|| count_valid(CharReader::reversed(Cursor::new(input.as_bytes()))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_readers);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use adv2020_4::{generate_passports, Validator};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const SIZE: usize = 1_000_000;
+
+// Counts the same million passports sequentially and in parallel. The counts are checked to be
+// identical before anything is measured
+fn bench_count(c: &mut Criterion) {
+    let passports = generate_passports(SIZE, 0.5, 2020);
+    let validator = Validator::default();
+    assert_eq!(validator.count(&passports), validator.par_count(&passports));
+
+    let mut group = c.benchmark_group("count");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(SIZE as u64));
+    group.bench_function("sequential", |b| b.iter(|| validator.count(&passports)));
+    group.bench_function("parallel", |b| b.iter(|| validator.par_count(&passports)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_count);
+criterion_main!(benches);
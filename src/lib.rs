@@ -0,0 +1,3105 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::{self, Formatter};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Read};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::{error, result, str};
+
+use indexmap::IndexMap;
+use lazy_static::*;
+pub use regex::Regex;
+
+pub mod validators;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Open(PathBuf, io::Error),
+    // The partial key, or key and partial value, which was being read is kept for context
+    NewlineInKey(Position, String),
+    ColonInValue(Position, String, String),
+    EofInKey(Position, String),
+    DuplicateKey(String),
+    EmptyKey,
+    LineTooLong(usize),
+    TooManyFields(usize),
+    Config(String),
+    NoPassport,
+    MultiplePassports,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Open(path, e) => write!(f, "io error opening '{}': {}", path.display(), e),
+            Error::NewlineInKey(position, key) => {
+                write!(f, "newline in key {:?} at {}", key, position)
+            }
+            Error::ColonInValue(position, key, value) => {
+                write!(f, "colon in value {:?} of key {:?} at {}", value, key, position)
+            }
+            Error::EofInKey(position, key) => {
+                write!(f, "end of file in key {:?} at {}", key, position)
+            }
+            Error::DuplicateKey(key) => write!(f, "duplicate key {}", key),
+            Error::EmptyKey => write!(f, "empty key"),
+            Error::LineTooLong(line) => write!(f, "line {} is too long", line),
+            Error::TooManyFields(line) => {
+                write!(f, "record starting on line {} has too many fields", line)
+            }
+            Error::Config(message) => write!(f, "invalid config: {}", message),
+            Error::NoPassport => write!(f, "no passport"),
+            Error::MultiplePassports => write!(f, "more than one passport"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Open(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! error_from {
+    ($kind:path, $variant:ident) => {
+        impl From<$kind> for Error {
+            fn from(e: $kind) -> Error {
+                Error::$variant(e)
+            }
+        }
+    };
+}
+
+error_from!(io::Error, Io);
+
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+// Replace a windows \r\n line ending with \n so the rest of the pipeline only sees \n
+fn normalize_line_ending(mut line: String) -> String {
+    if line.ends_with("\r\n") {
+        line.truncate(line.len() - 2);
+        line.push('\n');
+    }
+    line
+}
+
+enum LineBuffer {
+    // The line in its natural order with the byte index of the next character
+    Forward(String, usize),
+    // The characters of the line in reverse order, so that popping them off the end yields the
+    // line from the front. Decoding to chars up front means no reversed String is built
+    Reversed(Vec<char>),
+}
+
+impl LineBuffer {
+    fn next_char(&mut self) -> Option<char> {
+        match self {
+            LineBuffer::Forward(line, pos) => {
+                let c = line[*pos..].chars().next()?;
+                *pos += c.len_utf8();
+                Some(c)
+            }
+            LineBuffer::Reversed(chars) => chars.pop(),
+        }
+    }
+}
+
+enum CharReaderState<R: BufRead> {
+    Reader(R),
+    ReaderLine(R, LineBuffer),
+    Done,
+}
+
+pub struct CharReader<R: BufRead> {
+    state: Option<CharReaderState<R>>,
+    reversed: bool,
+    line: usize,
+    column: usize,
+    max_line_len: Option<usize>,
+}
+
+// For readers which are not buffered, such as a File, which are wrapped in a BufReader
+impl<R: Read> CharReader<io::BufReader<R>> {
+    pub fn from_read(reader: R) -> CharReader<io::BufReader<R>> {
+        CharReader::new(io::BufReader::new(reader))
+    }
+
+    pub fn reversed_from_read(reader: R) -> CharReader<io::BufReader<R>> {
+        CharReader::reversed(io::BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> CharReader<R> {
+    pub fn new(reader: R) -> CharReader<R> {
+        CharReader::with_mode(reader, false)
+    }
+
+    // The original implementation which pops characters from the end of each reversed line.
+    // Kept for comparison with the forward reader.
+    pub fn reversed(reader: R) -> CharReader<R> {
+        CharReader::with_mode(reader, true)
+    }
+
+    fn with_mode(reader: R, reversed: bool) -> CharReader<R> {
+        CharReader {
+            state: Some(CharReaderState::Reader(reader)),
+            reversed,
+            line: 0,
+            column: 0,
+            max_line_len: None,
+        }
+    }
+
+    // Lines longer than max bytes, not counting a \n or \r\n line ending, produce an error. At most
+    // max + 2 bytes are buffered for a line so input without newlines cannot exhaust memory
+    pub fn with_max_line_len(mut self, max: usize) -> CharReader<R> {
+        self.max_line_len = Some(max);
+        self
+    }
+
+    fn read_line(&self, reader: &mut R) -> Result<Option<String>> {
+        let mut buf = Vec::new();
+        let read = match self.max_line_len {
+            Some(max) => reader.take(max as u64 + 2).read_until(b'\n', &mut buf)?,
+            None => reader.read_until(b'\n', &mut buf)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if let Some(max) = self.max_line_len {
+            let ending = if buf.ends_with(b"\r\n") {
+                2
+            } else {
+                buf.ends_with(b"\n") as usize
+            };
+            let len = buf.len() - ending;
+            if len > max {
+                return Err(Error::LineTooLong(self.line + 1));
+            }
+        }
+        String::from_utf8(buf)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+
+    // The 1 based line number of the most recently read character
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    // The 1 based line and column of the most recently read character
+    pub fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn line_buffer(&self, line: String) -> LineBuffer {
+        let line = normalize_line_ending(line);
+        if self.reversed {
+            LineBuffer::Reversed(line.chars().rev().collect())
+        } else {
+            LineBuffer::Forward(line, 0)
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CharReader<R> {
+    type Item = Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Each call loops until a returnable state is reached.
+        // The outer match takes ownership of the state.
+        // Each branch MUST replace the state. To not do so is a logic error.
+        loop {
+            match self.state.take() {
+                // A branch has not replaced the state
+                None => panic!("none is not a valid state outside the next function"),
+
+                // The underlying reader reached its end state
+                Some(CharReaderState::Done) => {
+                    self.state = Some(CharReaderState::Done);
+                    return None
+                },
+
+                // There is an underlying reader but no line buffer so
+                // try to read a line and on success update the state
+                Some(CharReaderState::Reader(mut reader)) => {
+                    match self.read_line(&mut reader) {
+                        Ok(None) => self.state = Some(CharReaderState::Done),
+                        Ok(Some(buf)) => {
+                            self.line += 1;
+                            self.column = 0;
+                            // Comment lines are dropped entirely so they do not act as a record break
+                            if is_comment(&buf) {
+                                self.state = Some(CharReaderState::Reader(reader))
+                            } else {
+                                self.state = Some(CharReaderState::ReaderLine(reader, self.line_buffer(buf)))
+                            }
+                        },
+                        // This includes input which is not valid utf-8. The reader is not
+                        // read from again after an error
+                        Err(e) => {
+                            self.state = Some(CharReaderState::Done);
+                            return Some(Err(e))
+                        }
+                    }
+                },
+
+                // There is a reader and a line buffer so try to take a character from the
+                // line buffer and update the state, otherwise reset the state to having no current line buffer
+                Some(CharReaderState::ReaderLine(reader, mut line)) => {
+                    if let Some(c) = line.next_char() {
+                        self.column += 1;
+                        self.state = Some(CharReaderState::ReaderLine(reader, line));
+                        return Some(Ok(c))
+                    } else {
+                        self.state = Some(CharReaderState::Reader(reader))
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Opens a file, keeping its path in the error when it cannot be opened
+fn open_file<P: AsRef<Path>>(path: P) -> Result<File> {
+    let path = path.as_ref();
+    File::open(path).map_err(|e| Error::Open(path.to_path_buf(), e))
+}
+
+pub fn open_char_reader<P: AsRef<Path>>(filename: P) -> Result<CharReader<io::BufReader<File>>> {
+    let file = open_file(filename)?;
+    Ok(CharReader::from_read(file))
+}
+
+#[cfg(feature = "gzip")]
+pub fn open_char_reader_gz<P: AsRef<Path>>(
+    filename: P,
+) -> Result<CharReader<io::BufReader<flate2::read::GzDecoder<File>>>> {
+    let file = open_file(filename)?;
+    Ok(CharReader::from_read(flate2::read::GzDecoder::new(file)))
+}
+
+#[cfg(feature = "zstd")]
+pub fn open_char_reader_zst<P: AsRef<Path>>(
+    filename: P,
+) -> Result<CharReader<io::BufReader<zstd::Decoder<'static, io::BufReader<File>>>>> {
+    let file = open_file(filename)?;
+    Ok(CharReader::from_read(zstd::Decoder::new(file)?))
+}
+
+// A single key:value field read by Tokens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pair {
+    pub key: String,
+    pub value: String,
+}
+
+impl Pair {
+    fn new(key: String, value: String) -> Pair {
+        Pair { key, value }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+// How one record is separated from the next
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum RecordSeparator {
+    #[default]
+    BlankLine,
+    // A line holding only the given text. Blank lines are then ignored
+    Line(String),
+}
+
+pub struct Tokens<R: BufRead> {
+    chars: CharReader<R>,
+    peeked: Option<Option<Result<Token>>>,
+    separator: RecordSeparator,
+    // Every character read, kept only while capturing the raw text of records
+    raw: Option<String>,
+    trim: bool,
+    recover: bool,
+    // Whether the last pair was followed by whitespace rather than the end of its line
+    pair_on_line: bool,
+    // Whether the last character read ended a line, or nothing has been read yet
+    line_start: bool,
+    // The line and error of each malformed pair skipped while recovering
+    errors: Vec<(usize, Error)>,
+}
+
+impl<R: BufRead> Tokens<R> {
+    pub fn new(chars: CharReader<R>) -> Tokens<R> {
+        Tokens {
+            chars,
+            peeked: None,
+            separator: RecordSeparator::default(),
+            raw: None,
+            trim: false,
+            recover: false,
+            pair_on_line: false,
+            line_start: true,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_separator(mut self, separator: RecordSeparator) -> Tokens<R> {
+        self.separator = separator;
+        self
+    }
+
+    // Trim the whitespace around keys and values, so that byr : 1980 reads as byr:1980. A value
+    // of only whitespace reads as empty, and a value which is left out, as in byr: ecl:brn,
+    // takes the next field as its value and fails with a colon in value error
+    pub fn with_trimming(mut self, trim: bool) -> Tokens<R> {
+        self.trim = trim;
+        self
+    }
+
+    // When recovering, a malformed pair is skipped along with the rest of its line rather than
+    // returned as an error, and reading resumes on the next line. The other pairs of the record
+    // are kept. Io errors are still returned
+    pub fn with_recovery(mut self, recover: bool) -> Tokens<R> {
+        self.recover = recover;
+        self
+    }
+
+    // The errors skipped so far while recovering
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+
+    // Every key and value in the input, ignoring which record they belong to
+    pub fn pairs(self) -> impl Iterator<Item = Result<(String, String)>> {
+        self.filter_map(|token| match token {
+            Ok(Token::Pair(pair)) => Some(Ok((pair.key, pair.value))),
+            Ok(Token::Break) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    // Returns the next token without consuming it. A following call to next returns the same token
+    pub fn peek(&mut self) -> Option<&Result<Token>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read_token());
+        }
+        self.peeked.as_ref().and_then(|token| token.as_ref())
+    }
+
+    // The line of the underlying reader which the most recent token was read from
+    pub fn line(&self) -> usize {
+        self.chars.line()
+    }
+
+    fn read_char(&mut self) -> Option<Result<char>> {
+        let c = self.chars.next();
+        if let Some(Ok(c)) = c.as_ref() {
+            self.line_start = *c == '\n';
+            if let Some(raw) = self.raw.as_mut() {
+                raw.push(*c);
+            }
+        }
+        c
+    }
+
+    // Discards the rest of the record being read along with the line which ends it. This works
+    // on characters rather than tokens, since the remains of a malformed pair would otherwise be
+    // read as pairs or as a break of their own
+    fn skip_record(&mut self) -> Result<()> {
+        match self.peeked.take() {
+            Some(Some(Ok(Token::Break))) | Some(None) => return Ok(()),
+            Some(Some(Err(e))) => return Err(e),
+            Some(Some(Ok(Token::Pair(_)))) | None => (),
+        }
+        self.pair_on_line = false;
+        // Only a whole line can end the record, so the rest of a line which was partly read
+        // is dropped first
+        let mut whole_line = self.line_start;
+        let mut line = String::new();
+        loop {
+            match self.read_char() {
+                None => return Ok(()),
+                Some(Err(e)) => return Err(e),
+                Some(Ok('\n')) => {
+                    if whole_line && self.ends_record(&line) {
+                        return Ok(());
+                    }
+                    whole_line = true;
+                    line.clear();
+                }
+                Some(Ok(c)) => line.push(c),
+            }
+        }
+    }
+
+    fn ends_record(&self, line: &str) -> bool {
+        match &self.separator {
+            RecordSeparator::BlankLine => line.chars().all(is_separator),
+            RecordSeparator::Line(_) => self.is_separator_line(line),
+        }
+    }
+
+    fn read_token(&mut self) -> Option<Result<Token>> {
+        loop {
+            // Runs of separators between pairs are skipped so they never start an empty pair
+            let c = loop {
+                match self.read_char()? {
+                    Ok(c) if is_separator(c) => (),
+                    Ok('\n') if self.separator != RecordSeparator::BlankLine => (),
+                    // Ends a line of pairs which had trailing whitespace, rather than a record
+                    Ok('\n') if self.pair_on_line => self.pair_on_line = false,
+                    Ok(c) => break c,
+                    Err(e) => return Some(Err(e)),
+                }
+            };
+            let token = match c {
+                '\n' => Ok(Token::Break),
+                _ => self.parse_pair(c),
+            };
+            match token {
+                // The skipped pair ended its line, so a following blank line ends the record
+                Err(e @ Error::NewlineInKey(..)) | Err(e @ Error::EofInKey(..)) if self.recover => {
+                    self.errors.push((self.chars.line(), e));
+                    self.pair_on_line = false;
+                }
+                // The rest of the line is dropped along with the pair
+                Err(e @ Error::ColonInValue(..)) if self.recover => {
+                    self.errors.push((self.chars.line(), e));
+                    self.pair_on_line = false;
+                    loop {
+                        match self.read_char() {
+                            Some(Ok('\n')) | None => break,
+                            Some(Ok(_)) => (),
+                            Some(Err(e)) => return Some(Err(e)),
+                        }
+                    }
+                }
+                token => return Some(token),
+            }
+        }
+    }
+
+    fn parse_pair(&mut self, initial: char) -> Result<Token> {
+        let at_line_start = self.chars.position().column == 1;
+        let mut key = format!("{}", initial);
+        let mut value = String::new();
+        loop {
+            match self.read_char().transpose()? {
+                Some(':') => break,
+                Some('\n') | None if at_line_start && self.is_separator_line(&key) => {
+                    return Ok(Token::Break)
+                }
+                Some('\n') => return Err(Error::NewlineInKey(self.chars.position(), key)),
+                None => return Err(Error::EofInKey(self.chars.position(), key)),
+                Some(c) => key.push(c),
+            }
+        }
+        if self.trim {
+            key.truncate(key.trim_end_matches(|c: char| c.is_ascii_whitespace()).len());
+        }
+        while let Some(c) = self.read_char().transpose()? {
+            match c {
+                c if is_separator(c) && self.trim && value.is_empty() => (),
+                '\n' => {
+                    self.pair_on_line = false;
+                    break;
+                }
+                c if is_separator(c) => {
+                    self.pair_on_line = true;
+                    break;
+                }
+                ':' => return Err(Error::ColonInValue(self.chars.position(), key, value)),
+                _ => value.push(c),
+            }
+        }
+        Ok(Token::Pair(Pair::new(key, value)))
+    }
+
+    fn is_separator_line(&self, line: &str) -> bool {
+        match &self.separator {
+            RecordSeparator::BlankLine => false,
+            RecordSeparator::Line(separator) => line.trim_end() == separator,
+        }
+    }
+}
+
+/// Either a field, or the blank line which ends a record
+///
+/// ```
+/// use adv2020_4::{CharReader, IntoTokens, Pair, Token};
+///
+/// let mut keys = Vec::new();
+/// for token in CharReader::new("byr:1980 pid:1\n\necl:brn\n".as_bytes()).into_tokens() {
+///     match token.unwrap() {
+///         Token::Pair(Pair { key, value }) => keys.push(format!("{}={}", key, value)),
+///         Token::Break => keys.push("-".to_string()),
+///     }
+/// }
+/// assert_eq!(keys, ["byr=1980", "pid=1", "-", "ecl=brn"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Pair(Pair),
+    Break,
+}
+
+impl<R: BufRead> Iterator for Tokens<R> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Result<Token>> {
+        match self.peeked.take() {
+            Some(token) => token,
+            None => self.read_token(),
+        }
+    }
+}
+
+pub trait IntoTokens<R: BufRead> {
+    fn into_tokens(self) -> Tokens<R>;
+}
+
+
+impl<R: BufRead> IntoTokens<R> for CharReader<R> {
+    fn into_tokens(self) -> Tokens<R> {
+        Tokens::new(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Passport {
+    pairs: IndexMap<String, String>,
+    line: Option<usize>,
+}
+
+const CID: &str = "cid";
+const BYR: &str = "byr";
+const IYR: &str = "iyr";
+const EYR: &str = "eyr";
+const HGT: &str = "hgt";
+const HCL: &str = "hcl";
+const ECL: &str = "ecl";
+const PID: &str = "pid";
+
+const REQUIRED_FIELDS: [&str; 7] = [BYR, IYR, EYR, HGT, HCL, ECL, PID];
+
+lazy_static! {
+    static ref REQUIRED: Vec<String> =
+        REQUIRED_FIELDS.iter().map(|field| field.to_string()).collect();
+    static ref HGT_REGEX: Regex =
+        Regex::new(r"^(?P<amount>\d+)(?P<unit>cm|in)$").expect("invalid height regex");
+    static ref HGT_STRICT_REGEX: Regex = Regex::new(r"^(?P<amount>[1-9]\d*)(?P<unit>cm|in)$")
+        .expect("invalid strict height regex");
+    static ref HGT_FEET_REGEX: Regex = Regex::new(r"^(?P<feet>\d+)ft(?:(?P<inches>\d+)in)?$")
+        .expect("invalid feet and inches height regex");
+    static ref HCL_REGEX: Regex = Regex::new(r"^#[a-f0-9]{6}$").expect("invalid hair color regex");
+    static ref ECL_REGEX: Regex =
+        Regex::new(r"^(?:amb|blu|brn|gry|grn|hzl|oth)$").expect("invalid eye color regex");
+    static ref PID_REGEX: Regex = Regex::new(r"^\d{9}$").expect("invalid passport id regex");
+    static ref DEFAULT_VALIDATOR: Validator = Validator::default();
+}
+
+impl Passport {
+    fn new(pairs: IndexMap<String, String>, line: Option<usize>) -> Passport {
+        Passport { pairs, line }
+    }
+
+    pub fn builder() -> PassportBuilder {
+        PassportBuilder::new()
+    }
+
+    // The line of the input on which the passport record started, if it was parsed from input
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    // An owned copy of the fields, sorted by key
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.pairs
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn field_count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    // Always false for passports which were parsed, since a record has at least one field
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.get(key).map(|value| value.as_str())
+    }
+
+    // Every part of a field which packs several values separated by ;, such as hobbies:ski;surf.
+    // A field with a single value yields just that value and a missing field yields nothing
+    pub fn values(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.values_split_by(key, ';')
+    }
+
+    pub fn values_split_by(&self, key: &str, delimiter: char) -> impl Iterator<Item = &str> {
+        self.get(key).into_iter().flat_map(move |value| value.split(delimiter))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    // A copy with every value trimmed and ecl and hcl lowercased, for comparing records which
+    // differ only in how they were written
+    pub fn normalized(&self) -> Passport {
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|(key, value)| {
+                let value = value.trim();
+                let value = if key == ECL || key == HCL {
+                    value.to_lowercase()
+                } else {
+                    value.to_string()
+                };
+                (key.clone(), value)
+            })
+            .collect();
+        Passport::new(pairs, self.line)
+    }
+
+    // Folds the fields of another passport into this one, for a passport which was split across
+    // two records. The policy decides which value is kept for a key which both passports have.
+    // New keys are added after the existing ones and the earlier start line is kept
+    pub fn merge(&mut self, other: Passport, policy: MergePolicy) {
+        for (key, value) in other.pairs {
+            match policy {
+                MergePolicy::KeepExisting => {
+                    self.pairs.entry(key).or_insert(value);
+                }
+                MergePolicy::Replace => {
+                    self.pairs.insert(key, value);
+                }
+            }
+        }
+        self.line = match (self.line, other.line) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    // The required fields which the passport lacks, in the order byr, iyr, eyr, hgt, hcl, ecl, pid.
+    // A missing cid is not included since north pole credentials do not need one
+    pub fn missing_required_fields(&self) -> Vec<&'static str> {
+        REQUIRED_FIELDS
+            .iter()
+            .copied()
+            .filter(|field| !self.pairs.contains_key(*field))
+            .collect()
+    }
+
+    // Unknown extra fields are allowed alongside the required fields, and cid is optional
+    pub fn contains_required_fields(&self) -> bool {
+        self.contains_min_fields()
+    }
+
+    // Only genuine passports, which must have a cid, rather than north pole credentials
+    pub fn contains_strict_passport_fields(&self) -> bool {
+        self.contains_passport_required_fields()
+    }
+
+    pub fn contains_required_fields_with(&self, config: &ValidationConfig) -> bool {
+        self.contains_fields(&config.required, config.required_fields_policy)
+    }
+
+    fn contains_fields(&self, required: &[String], policy: RequiredFieldsPolicy) -> bool {
+        required.iter().all(|key| self.pairs.contains_key(key))
+            && (policy == RequiredFieldsPolicy::AllowMissingCid || self.contains_cid_field())
+    }
+
+    fn contains_passport_required_fields(&self) -> bool {
+        self.contains_min_fields() && self.contains_cid_field()
+    }
+
+    fn contains_min_fields(&self) -> bool {
+        REQUIRED.iter().all(|key| self.pairs.contains_key(key))
+    }
+
+    fn contains_cid_field(&self) -> bool {
+        self.pairs.contains_key(CID)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_valid()
+    }
+
+    // Classifies the passport, only validating it when it has the required fields
+    pub fn status(&self) -> PassportStatus {
+        DEFAULT_VALIDATOR.status(self)
+    }
+
+    pub fn status_with(&self, config: &ValidationConfig) -> PassportStatus {
+        Validator::from(config).status(self)
+    }
+
+    pub fn validate(&self) -> ValidationReport {
+        DEFAULT_VALIDATOR.validate(self)
+    }
+
+    pub fn is_valid_with(&self, config: &ValidationConfig) -> bool {
+        self.validate_with(config).is_valid()
+    }
+
+    pub fn validate_with(&self, config: &ValidationConfig) -> ValidationReport {
+        Validator::from(config).validate(self)
+    }
+}
+
+// Fields are written in the order they were read, separated by spaces
+impl fmt::Display for Passport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let out = self
+            .pairs
+            .iter()
+            .map(|(k, v)| format!("{}:{}", k, v))
+            .collect::<Vec<String>>()
+            .join(" ");
+        write!(f, "{}", out)
+    }
+}
+
+// Passports are equal when they have the same fields, whatever their order or start line
+impl PartialEq for Passport {
+    fn eq(&self, other: &Passport) -> bool {
+        self.pairs == other.pairs
+    }
+}
+
+impl Eq for Passport {}
+
+impl Hash for Passport {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pairs = self.pairs.iter().collect::<Vec<(&String, &String)>>();
+        pairs.sort();
+        pairs.hash(state);
+    }
+}
+
+// Which value Passport::merge keeps when both passports have the same key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    KeepExisting,
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassportStatus {
+    Missing,
+    RequiredButInvalid,
+    Valid,
+}
+
+impl str::FromStr for Passport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Passport> {
+        let mut passports = CharReader::new(s.as_bytes())
+            .into_tokens()
+            .into_passports();
+        match (passports.next(), passports.next()) {
+            (Some(Ok(passport)), None) => Ok(passport),
+            (Some(Err(e)), _) | (Some(Ok(_)), Some(Err(e))) => Err(e),
+            (Some(Ok(_)), Some(Ok(_))) => Err(Error::MultiplePassports),
+            (None, _) => Err(Error::NoPassport),
+        }
+    }
+}
+
+// The fields are sorted by key since a HashMap has no order of its own
+impl TryFrom<HashMap<String, String>> for Passport {
+    type Error = Error;
+
+    fn try_from(map: HashMap<String, String>) -> Result<Passport> {
+        if map.contains_key("") {
+            return Err(Error::EmptyKey);
+        }
+        let mut pairs = map.into_iter().collect::<Vec<(String, String)>>();
+        pairs.sort();
+        Ok(Passport::new(pairs.into_iter().collect(), None))
+    }
+}
+
+#[derive(Default)]
+pub struct PassportBuilder {
+    pairs: IndexMap<String, String>,
+}
+
+impl PassportBuilder {
+    pub fn new() -> PassportBuilder {
+        PassportBuilder::default()
+    }
+
+    // Sets any field, including ones which are not known to the validators
+    pub fn field(mut self, key: &str, value: &str) -> PassportBuilder {
+        self.pairs.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn build(self) -> Passport {
+        Passport::new(self.pairs, None)
+    }
+}
+
+macro_rules! builder_fields {
+    ($($name:ident => $key:ident),*) => {
+        impl PassportBuilder {
+            $(
+                pub fn $name(self, value: &str) -> PassportBuilder {
+                    self.field($key, value)
+                }
+            )*
+        }
+    };
+}
+
+builder_fields!(
+    byr => BYR,
+    iyr => IYR,
+    eyr => EYR,
+    hgt => HGT,
+    hcl => HCL,
+    ecl => ECL,
+    pid => PID,
+    cid => CID
+);
+
+// SplitMix64, which is enough to vary generated passports without taking on a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in from..=to
+    fn range(&mut self, from: u64, to: u64) -> u64 {
+        from + self.next_u64() % (to - from + 1)
+    }
+
+    // A number in [0, 1)
+    fn ratio(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+const EYE_COLORS: [&str; 7] = ["amb", "blu", "brn", "gry", "grn", "hzl", "oth"];
+
+// Random passports for load testing, where about valid_ratio of them are valid under the built
+// in rules. The same seed always gives the same passports. Each invalid passport has exactly one
+// required field which is either left out or has a value the built in rules reject
+pub fn generate_passports(n: usize, valid_ratio: f64, seed: u64) -> Vec<Passport> {
+    let mut rng = Rng(seed);
+    let mut passports = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut fields = vec![
+            (BYR, rng.range(1920, 2002).to_string()),
+            (IYR, rng.range(2010, 2020).to_string()),
+            (EYR, rng.range(2020, 2030).to_string()),
+            (
+                HGT,
+                if rng.range(0, 1) == 0 {
+                    format!("{}cm", rng.range(150, 193))
+                } else {
+                    format!("{}in", rng.range(59, 76))
+                },
+            ),
+            (HCL, format!("#{:06x}", rng.range(0, 0xffffff))),
+            (ECL, EYE_COLORS[rng.range(0, 6) as usize].to_string()),
+            (PID, format!("{:09}", rng.range(0, 999_999_999))),
+        ];
+        if rng.ratio() >= valid_ratio {
+            let field = rng.range(0, 6) as usize;
+            if rng.range(0, 1) == 0 {
+                fields.remove(field);
+            } else {
+                fields[field].1 = match fields[field].0 {
+                    BYR => rng.range(2003, 2099).to_string(),
+                    IYR => rng.range(1900, 2009).to_string(),
+                    EYR => rng.range(2031, 2099).to_string(),
+                    HGT => format!("{}cm", rng.range(194, 250)),
+                    HCL => format!("{:06x}", rng.range(0, 0xffffff)),
+                    ECL => "xxx".to_string(),
+                    _ => format!("{:08}", rng.range(0, 99_999_999)),
+                };
+            }
+        }
+        if rng.range(0, 1) == 0 {
+            fields.push((CID, rng.range(1, 999).to_string()));
+        }
+        let builder = fields
+            .iter()
+            .fold(PassportBuilder::new(), |builder, (key, value)| builder.field(key, value));
+        passports.push(builder.build());
+    }
+    passports
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Passport {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.pairs.iter())
+    }
+}
+
+// The inverse of Serialize, keeping the order of the fields. An empty key is rejected as it is
+// when converting from a HashMap
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Passport {
+    fn deserialize<D>(deserializer: D) -> result::Result<Passport, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let pairs = IndexMap::<String, String>::deserialize(deserializer)?;
+        if pairs.contains_key("") {
+            return Err(serde::de::Error::custom(Error::EmptyKey));
+        }
+        Ok(Passport::new(pairs, None))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldStatus {
+    Valid,
+    Invalid(String),
+    Missing,
+}
+
+#[derive(Debug)]
+pub struct ValidationReport {
+    fields: Vec<(String, FieldStatus)>,
+}
+
+impl ValidationReport {
+    fn new(fields: Vec<(String, FieldStatus)>) -> ValidationReport {
+        ValidationReport { fields }
+    }
+
+    fn invalidate(&mut self, field: &str, value: String) {
+        match self.fields.iter_mut().find(|(name, _)| name == field) {
+            Some((_, status)) => *status = FieldStatus::Invalid(value),
+            None => self
+                .fields
+                .push((field.to_string(), FieldStatus::Invalid(value))),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|(_, status)| *status == FieldStatus::Valid)
+    }
+
+    pub fn field(&self, field: &str) -> Option<&FieldStatus> {
+        self.fields
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, status)| status)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &FieldStatus)> {
+        self.fields.iter().map(|(name, status)| (name.as_str(), status))
+    }
+}
+
+// A rule for the value of a single passport field
+pub trait FieldValidator: Send + Sync {
+    fn field(&self) -> &str;
+    fn is_valid(&self, value: &str) -> bool;
+
+    // An optional field is only checked when it is present
+    fn is_required(&self) -> bool {
+        true
+    }
+}
+
+// A year which must fall within an inclusive range
+pub struct YearValidator {
+    field: String,
+    from: u32,
+    to: u32,
+    optional: bool,
+}
+
+impl YearValidator {
+    pub fn new(field: &str, from: u32, to: u32) -> YearValidator {
+        YearValidator {
+            field: field.to_string(),
+            from,
+            to,
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> YearValidator {
+        self.optional = true;
+        self
+    }
+}
+
+impl FieldValidator for YearValidator {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn is_valid(&self, value: &str) -> bool {
+        validators::year_in_range(value, (self.from, self.to))
+    }
+
+    fn is_required(&self) -> bool {
+        !self.optional
+    }
+}
+
+// A height in either cm or in, each with its own inclusive range
+pub struct HeightValidator {
+    field: String,
+    cm: (u32, u32),
+    inches: (u32, u32),
+    feet_and_inches: bool,
+    strict_amount: bool,
+    optional: bool,
+}
+
+impl HeightValidator {
+    pub fn new(field: &str, cm: (u32, u32), inches: (u32, u32)) -> HeightValidator {
+        HeightValidator {
+            field: field.to_string(),
+            cm,
+            inches,
+            feet_and_inches: false,
+            strict_amount: false,
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> HeightValidator {
+        self.optional = true;
+        self
+    }
+
+    // Also accept heights such as 5ft10in or 6ft, which are checked against the inch range
+    pub fn with_feet_and_inches(mut self, allow: bool) -> HeightValidator {
+        self.feet_and_inches = allow;
+        self
+    }
+
+    // Reject amounts with leading zeros, such as 0150cm
+    pub fn with_strict_amount(mut self, strict: bool) -> HeightValidator {
+        self.strict_amount = strict;
+        self
+    }
+}
+
+impl FieldValidator for HeightValidator {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn is_valid(&self, value: &str) -> bool {
+        if self.feet_and_inches {
+            if let Some(inches) = validators::parse_feet_and_inches(value) {
+                return validators::in_range(inches, self.inches);
+            }
+        }
+        validators::parse_height(value, self.strict_amount)
+            .is_some_and(|height| validators::height_in_range(height, self.cm, self.inches))
+    }
+
+    fn is_required(&self) -> bool {
+        !self.optional
+    }
+}
+
+// A value which must match a regular expression
+pub struct RegexValidator {
+    field: String,
+    regex: Regex,
+    lowercase: bool,
+    pad_width: Option<usize>,
+    optional: bool,
+}
+
+impl RegexValidator {
+    pub fn new(field: &str, regex: Regex) -> RegexValidator {
+        RegexValidator {
+            field: field.to_string(),
+            regex,
+            lowercase: false,
+            pad_width: None,
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> RegexValidator {
+        self.optional = true;
+        self
+    }
+
+    // Lowercase values before matching so that the match is case insensitive
+    pub fn lowercase_values(mut self) -> RegexValidator {
+        self.lowercase = true;
+        self
+    }
+
+    // Left pad values made up only of digits with zeros to the given width before matching,
+    // for numbers which have lost their leading zeros
+    pub fn zero_pad_numbers(mut self, width: usize) -> RegexValidator {
+        self.pad_width = Some(width);
+        self
+    }
+}
+
+impl FieldValidator for RegexValidator {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn is_valid(&self, value: &str) -> bool {
+        let value = match self.pad_width {
+            Some(width) if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                format!("{:0>width$}", value, width = width)
+            }
+            _ => value.to_string(),
+        };
+        if self.lowercase {
+            self.regex.is_match(&value.to_lowercase())
+        } else {
+            self.regex.is_match(&value)
+        }
+    }
+
+    fn is_required(&self) -> bool {
+        !self.optional
+    }
+}
+
+// A set of field validators, all of which must pass for a passport to be valid.
+// The default validator applies the built in rules
+pub struct Validator {
+    validators: Vec<Box<dyn FieldValidator>>,
+    check_year_order: bool,
+    required: Vec<String>,
+    required_fields_policy: RequiredFieldsPolicy,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator {
+            validators: Vec::new(),
+            check_year_order: false,
+            required: Vec::new(),
+            required_fields_policy: RequiredFieldsPolicy::default(),
+        }
+    }
+
+    pub fn has_required_fields(&self, passport: &Passport) -> bool {
+        passport.contains_fields(&self.required, self.required_fields_policy)
+    }
+
+    // Classifies a passport, only validating it when it has the required fields
+    pub fn status(&self, passport: &Passport) -> PassportStatus {
+        if !self.has_required_fields(passport) {
+            PassportStatus::Missing
+        } else if self.is_valid(passport) {
+            PassportStatus::Valid
+        } else {
+            PassportStatus::RequiredButInvalid
+        }
+    }
+
+    // Counts, for each validated field, how many passports failed because of that field
+    pub fn field_failures(&self, passports: &[Passport]) -> HashMap<String, usize> {
+        let mut failures = HashMap::new();
+        for passport in passports {
+            for (field, status) in self.validate(passport).iter() {
+                if *status != FieldStatus::Valid {
+                    *failures.entry(field.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        failures
+    }
+
+    // Also reject passports which expire before they were issued or were issued before the
+    // holder was born. This is stricter than the puzzle rules so is off by default
+    pub fn with_year_order_check(mut self, check: bool) -> Validator {
+        self.check_year_order = check;
+        self
+    }
+
+    // Adds a field validator, replacing any existing validator for the same field
+    pub fn with<V: FieldValidator + 'static>(mut self, validator: V) -> Validator {
+        self.validators.retain(|v| v.field() != validator.field());
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    pub fn validate(&self, passport: &Passport) -> ValidationReport {
+        let mut report = ValidationReport::new(
+            self.validators
+                .iter()
+                .filter_map(|validator| {
+                    let status = match passport.pairs.get(validator.field()) {
+                        None if !validator.is_required() => return None,
+                        None => FieldStatus::Missing,
+                        Some(value) if validator.is_valid(value) => FieldStatus::Valid,
+                        Some(value) => FieldStatus::Invalid(value.clone()),
+                    };
+                    Some((validator.field().to_string(), status))
+                })
+                .collect(),
+        );
+        if self.check_year_order {
+            Validator::validate_year_order(passport, &mut report);
+        }
+        report
+    }
+
+    fn validate_year_order(passport: &Passport, report: &mut ValidationReport) {
+        let year = |field| {
+            passport
+                .get(field)
+                .and_then(|value| validators::parse_year(value).map(|year| (year, value)))
+        };
+        let (byr, iyr, eyr) = (year(BYR), year(IYR), year(EYR));
+        if let (Some((iyr, _)), Some((eyr, value))) = (iyr, eyr) {
+            if eyr < iyr {
+                report.invalidate(EYR, value.to_string());
+            }
+        }
+        if let (Some((byr, value)), Some((iyr, _))) = (byr, iyr) {
+            if byr > iyr {
+                report.invalidate(BYR, value.to_string());
+            }
+        }
+    }
+
+    pub fn is_valid(&self, passport: &Passport) -> bool {
+        self.validate(passport).is_valid()
+    }
+
+    // Returns the number of passports with the required fields and the number which are also valid
+    pub fn count(&self, passports: &[Passport]) -> (usize, usize) {
+        passports
+            .iter()
+            .map(|p| tally(self.status(p)))
+            .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+    }
+
+    // The same counts as count, with the passports validated in parallel
+    #[cfg(feature = "parallel")]
+    pub fn par_count(&self, passports: &[Passport]) -> (usize, usize) {
+        use rayon::prelude::*;
+
+        passports
+            .par_iter()
+            .map(|p| tally(self.status(p)))
+            .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+    }
+
+    // Counts each eye color among the valid passports
+    pub fn ecl_histogram(&self, passports: &[Passport]) -> HashMap<String, usize> {
+        let mut colors = HashMap::new();
+        for passport in passports.iter().filter(|p| self.is_valid(p)) {
+            if let Some(ecl) = passport.get(ECL) {
+                *colors.entry(ecl.to_string()).or_insert(0) += 1;
+            }
+        }
+        colors
+    }
+
+    // Checks a single value against every rule for its field. None for a field without rules,
+    // such as cid or a misspelt field name, since there is nothing to check it against
+    pub fn validate_field(&self, field: &str, value: &str) -> Option<bool> {
+        let mut validators = self
+            .validators
+            .iter()
+            .filter(|validator| validator.field() == field)
+            .peekable();
+        validators.peek()?;
+        Some(validators.all(|validator| validator.is_valid(value)))
+    }
+
+    // Accept eye colors in any case, so ecl:BRN is treated like ecl:brn
+    pub fn case_insensitive_ecl(self) -> Validator {
+        self.with(RegexValidator::new(ECL, ECL_REGEX.clone()).lowercase_values())
+    }
+
+    // Accept hair colors with uppercase hex digits, so hcl:#ABC123 is treated like hcl:#abc123
+    pub fn case_insensitive_hcl(self) -> Validator {
+        self.with(RegexValidator::new(HCL, HCL_REGEX.clone()).lowercase_values())
+    }
+
+    // Accept numeric passport ids which have lost their leading zeros, so pid:12345678 is
+    // treated like pid:012345678. Ids longer than nine digits are still rejected
+    pub fn numeric_pid(self) -> Validator {
+        self.with(RegexValidator::new(PID, PID_REGEX.clone()).zero_pad_numbers(9))
+    }
+}
+
+// The required fields and valid counts which a single passport contributes
+fn tally(status: PassportStatus) -> (usize, usize) {
+    match status {
+        PassportStatus::Missing => (0, 0),
+        PassportStatus::RequiredButInvalid => (1, 0),
+        PassportStatus::Valid => (1, 1),
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Validator {
+        Validator::from(&ValidationConfig::default())
+    }
+}
+
+// Whether north pole credentials, which are passports without a cid, have the required fields.
+// In a rules file these are "allow_missing_cid" and "require_cid"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum RequiredFieldsPolicy {
+    #[default]
+    AllowMissingCid,
+    RequireCid,
+}
+
+// The required fields and the rules used by the built in validators. With the config feature
+// it can be loaded from a json file, where any setting which is left out keeps its default
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields))]
+pub struct ValidationConfig {
+    pub required: Vec<String>,
+    pub byr: (u32, u32),
+    pub iyr: (u32, u32),
+    pub eyr: (u32, u32),
+    pub hgt_cm: (u32, u32),
+    pub hgt_in: (u32, u32),
+    pub hgt_feet_and_inches: bool,
+    pub hgt_strict_amount: bool,
+    pub check_year_order: bool,
+    pub required_fields_policy: RequiredFieldsPolicy,
+    // Extra patterns which fields must match, checked in addition to the built in rules.
+    // Fields which are absent are not checked
+    #[cfg_attr(feature = "config", serde(deserialize_with = "deserialize_patterns"))]
+    pub patterns: HashMap<String, Regex>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> ValidationConfig {
+        ValidationConfig {
+            required: REQUIRED.clone(),
+            byr: validators::BYR_RANGE,
+            iyr: validators::IYR_RANGE,
+            eyr: validators::EYR_RANGE,
+            hgt_cm: validators::HGT_CM_RANGE,
+            hgt_in: validators::HGT_IN_RANGE,
+            hgt_feet_and_inches: false,
+            hgt_strict_amount: false,
+            check_year_order: false,
+            required_fields_policy: RequiredFieldsPolicy::default(),
+            patterns: HashMap::new(),
+        }
+    }
+}
+
+impl ValidationConfig {
+    // The built in rules with every stricter check turned on
+    pub fn strict() -> ValidationConfig {
+        ValidationConfig::default().into_strict()
+    }
+
+    // Turns on every stricter check: heights without leading zeros, a required cid, and years
+    // which are in order. Years must always be exactly four digits so there is nothing to change
+    // for them
+    pub fn into_strict(mut self) -> ValidationConfig {
+        self.hgt_strict_amount = true;
+        self.required_fields_policy = RequiredFieldsPolicy::RequireCid;
+        self.check_year_order = true;
+        self
+    }
+}
+
+#[cfg(feature = "config")]
+impl ValidationConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ValidationConfig> {
+        let file = open_file(path)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(|e| Error::Config(e.to_string()))
+    }
+}
+
+#[cfg(feature = "config")]
+fn deserialize_patterns<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> result::Result<HashMap<String, Regex>, D::Error> {
+    use serde::Deserialize;
+
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(field, pattern)| {
+            Regex::new(&pattern)
+                .map(|regex| (field, regex))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+// The built in rules for fields which the config does not require only check values which are
+// present, so a missing field is never reported for a passport which has the required fields
+impl From<&ValidationConfig> for Validator {
+    fn from(config: &ValidationConfig) -> Validator {
+        let required = |field: &str| config.required.iter().any(|key| key == field);
+        let byr = YearValidator::new(BYR, config.byr.0, config.byr.1);
+        let iyr = YearValidator::new(IYR, config.iyr.0, config.iyr.1);
+        let eyr = YearValidator::new(EYR, config.eyr.0, config.eyr.1);
+        let hgt = HeightValidator::new(HGT, config.hgt_cm, config.hgt_in)
+            .with_feet_and_inches(config.hgt_feet_and_inches)
+            .with_strict_amount(config.hgt_strict_amount);
+        let hcl = RegexValidator::new(HCL, HCL_REGEX.clone());
+        let ecl = RegexValidator::new(ECL, ECL_REGEX.clone());
+        let pid = RegexValidator::new(PID, PID_REGEX.clone());
+        let mut validator = Validator::new()
+            .with_year_order_check(config.check_year_order)
+            .with(if required(BYR) { byr } else { byr.optional() })
+            .with(if required(IYR) { iyr } else { iyr.optional() })
+            .with(if required(EYR) { eyr } else { eyr.optional() })
+            .with(if required(HGT) { hgt } else { hgt.optional() })
+            .with(if required(HCL) { hcl } else { hcl.optional() })
+            .with(if required(ECL) { ecl } else { ecl.optional() })
+            .with(if required(PID) { pid } else { pid.optional() });
+
+        validator.required = config.required.clone();
+        validator.required_fields_policy = config.required_fields_policy;
+
+        // Pushed rather than added with `with` so that they do not replace the built in rules
+        let mut fields = config.patterns.keys().collect::<Vec<&String>>();
+        fields.sort();
+        for field in fields {
+            let pattern = RegexValidator::new(field, config.patterns[field].clone()).optional();
+            validator.validators.push(Box::new(pattern));
+        }
+        validator
+    }
+}
+
+// Counts, for each validated field, how many passports failed because of that field
+pub fn field_failures(passports: &[Passport]) -> HashMap<String, usize> {
+    DEFAULT_VALIDATOR.field_failures(passports)
+}
+
+// The fields which sort_passports can order by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Pid,
+    Byr,
+    Iyr,
+}
+
+impl SortKey {
+    fn field(self) -> &'static str {
+        match self {
+            SortKey::Pid => PID,
+            SortKey::Byr => BYR,
+            SortKey::Iyr => IYR,
+        }
+    }
+}
+
+// Sorts on the values of one field, with the passports which lack it last. The sort is stable,
+// so sorting by byr and then by pid orders by pid then byr
+pub fn sort_passports(passports: &mut [Passport], by: SortKey) {
+    let field = by.field();
+    passports.sort_by(|a, b| {
+        let (a, b) = (a.get(field), b.get(field));
+        (a.is_none(), a).cmp(&(b.is_none(), b))
+    });
+}
+
+// Drops every passport with the same fields as an earlier one, keeping the order of the rest.
+// Returns the number which were dropped
+pub fn dedup_passports(passports: &mut Vec<Passport>) -> usize {
+    let before = passports.len();
+    let keep = {
+        let mut seen = HashSet::new();
+        passports.iter().map(|p| seen.insert(p)).collect::<Vec<bool>>()
+    };
+    let mut keep = keep.into_iter();
+    passports.retain(|_| keep.next().unwrap_or(true));
+    before - passports.len()
+}
+
+// Counts each eye color among the passports which are valid under the built in rules
+pub fn ecl_histogram(passports: &[Passport]) -> HashMap<String, usize> {
+    DEFAULT_VALIDATOR.ecl_histogram(passports)
+}
+
+// Checks a single value against the built in rules for its field, None if it has no rules
+pub fn validate_field(field: &str, value: &str) -> Option<bool> {
+    DEFAULT_VALIDATOR.validate_field(field, value)
+}
+
+pub fn num_with_required_fields(passports: &[Passport]) -> usize {
+    passports
+        .iter()
+        .filter(|p| p.contains_required_fields())
+        .count()
+}
+
+pub fn num_valid(passports: &[Passport]) -> usize {
+    passports.iter().filter(|p| p.is_valid()).count()
+}
+
+// Parses every passport in a string, stopping at the first error
+pub fn parse_passports(input: &str) -> Result<Vec<Passport>> {
+    Passports::from_reader(input.as_bytes()).collect()
+}
+
+// Parses every passport in a string, skipping malformed records and collecting their errors
+pub fn parse_lenient(input: &str) -> (Vec<Passport>, Vec<(usize, Error)>) {
+    Passports::from_reader(input.as_bytes()).collect_lenient()
+}
+
+// Counts all passports, the passports with the required fields and the valid passports while
+// only ever holding a single passport in memory
+pub fn count_passports<R: BufRead>(reader: R) -> Result<(usize, usize, usize)> {
+    let counts = Passports::from_reader(reader).count_by_status()?;
+    Ok((
+        counts.total,
+        counts.required_but_invalid + counts.valid,
+        counts.valid,
+    ))
+}
+
+// The number of passports with each status. The statuses do not overlap so they sum to the total
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub total: usize,
+    pub missing_fields: usize,
+    pub required_but_invalid: usize,
+    pub valid: usize,
+}
+
+impl StatusCounts {
+    fn add(&mut self, status: PassportStatus) {
+        self.total += 1;
+        match status {
+            PassportStatus::Missing => self.missing_fields += 1,
+            PassportStatus::RequiredButInvalid => self.required_but_invalid += 1,
+            PassportStatus::Valid => self.valid += 1,
+        }
+    }
+}
+
+// Checks that the input parses, returning the number of records or the first error. Only the
+// tokens are read, no passports are built
+pub fn check_parse<R: BufRead>(reader: R) -> Result<usize> {
+    let mut records = 0;
+    let mut in_record = false;
+    for token in CharReader::new(reader).into_tokens() {
+        match token? {
+            Token::Pair(_) => in_record = true,
+            Token::Break => {
+                if in_record {
+                    records += 1;
+                }
+                in_record = false;
+            }
+        }
+    }
+    if in_record {
+        records += 1;
+    }
+    Ok(records)
+}
+
+pub struct Passports<R: BufRead> {
+    tokens: Tokens<R>,
+    strict_keys: bool,
+    max_fields: Option<usize>,
+    // Set when an error is returned partway through a record, so that the rest of the record is
+    // skipped before the next one is read
+    broken: bool,
+    // Set once None has been returned so that it is returned from then on
+    done: bool,
+}
+
+// Opens a file and reads its passports, the common case in one call
+impl Passports<io::BufReader<File>> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Passports<io::BufReader<File>>> {
+        Ok(Passports::from_reader(io::BufReader::new(open_file(path)?)))
+    }
+}
+
+impl<R: BufRead> Passports<R> {
+    pub fn new(tokens: Tokens<R>) -> Passports<R> {
+        Passports {
+            tokens,
+            strict_keys: false,
+            max_fields: None,
+            broken: false,
+            done: false,
+        }
+    }
+
+    pub fn from_reader(reader: R) -> Passports<R> {
+        CharReader::new(reader).into_tokens().into_passports()
+    }
+
+    // When strict, a key repeated within one passport is an error rather than the last value winning
+    pub fn with_strict_keys(mut self, strict: bool) -> Passports<R> {
+        self.strict_keys = strict;
+        self
+    }
+
+    // A record with more than max fields produces an error so that a single huge record cannot
+    // exhaust memory. Fields are counted as they are read, repeated keys included
+    pub fn with_max_fields(mut self, max: usize) -> Passports<R> {
+        self.max_fields = Some(max);
+        self
+    }
+
+    // Splits records on the given separator rather than on blank lines
+    pub fn with_separator(mut self, separator: RecordSeparator) -> Passports<R> {
+        self.tokens = self.tokens.with_separator(separator);
+        self
+    }
+
+    // Skips malformed pairs rather than returning them as errors, see Tokens::with_recovery
+    pub fn with_recovery(mut self, recover: bool) -> Passports<R> {
+        self.tokens = self.tokens.with_recovery(recover);
+        self
+    }
+
+    pub fn errors(&self) -> &[(usize, Error)] {
+        self.tokens.errors()
+    }
+
+    // Yields each passport along with the text of the record it was parsed from. The blank or
+    // separator lines around the record are left out. The text is as the reader returned it, so
+    // comment lines are gone and line endings are \n
+    pub fn into_iter_with_raw(mut self) -> impl Iterator<Item = Result<(Passport, String)>> {
+        self.tokens.raw = Some(String::new());
+        iter::from_fn(move || {
+            let passport = self.next()?;
+            let raw = self.tokens.raw.replace(String::new()).unwrap_or_default();
+            let text = raw
+                .split_inclusive('\n')
+                .filter(|line| !line.trim().is_empty() && !self.tokens.is_separator_line(line))
+                .collect::<String>();
+            Some(passport.map(|passport| (passport, text)))
+        })
+    }
+
+    // Classifies every passport in a single pass, stopping at the first error
+    pub fn count_by_status(self) -> Result<StatusCounts> {
+        let mut counts = StatusCounts::default();
+        for passport in self {
+            counts.add(passport?.status());
+        }
+        Ok(counts)
+    }
+
+    // Lazily validates each passport with the built in rules, yielding its report
+    pub fn reports(self) -> impl Iterator<Item = Result<ValidationReport>> {
+        self.map(|passport| passport.map(|passport| passport.validate()))
+    }
+
+    pub fn reports_with<'a>(
+        self,
+        validator: &'a Validator,
+    ) -> impl Iterator<Item = Result<ValidationReport>> + 'a
+    where
+        R: 'a,
+    {
+        self.map(move |passport| passport.map(|passport| validator.validate(&passport)))
+    }
+
+    // Lazily yields only the valid passports. Errors are passed through rather than dropped
+    pub fn valid(self) -> impl Iterator<Item = Result<Passport>> {
+        self.filter(|passport| match passport {
+            Ok(passport) => passport.status() == PassportStatus::Valid,
+            Err(_) => true,
+        })
+    }
+
+    // Lazily yields only the passports with the required fields. Errors are passed through
+    pub fn with_required_fields(self) -> impl Iterator<Item = Result<Passport>> {
+        self.filter(|passport| match passport {
+            Ok(passport) => passport.status() != PassportStatus::Missing,
+            Err(_) => true,
+        })
+    }
+
+    // Reads every passport, skipping the rest of any record which fails to parse. Each error is
+    // returned with the line it was found on alongside the passports which parsed
+    pub fn collect_lenient(mut self) -> (Vec<Passport>, Vec<(usize, Error)>) {
+        let mut passports = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(passport) = self.next() {
+            match passport {
+                Ok(passport) => passports.push(passport),
+                Err(e) => errors.push((self.tokens.line(), e)),
+            }
+        }
+        (passports, errors)
+    }
+
+    fn error(&mut self, e: Error) -> Option<Result<Passport>> {
+        self.broken = true;
+        Some(Err(e))
+    }
+}
+
+impl<R: BufRead> Iterator for Passports<R> {
+    type Item = Result<Passport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        // Nothing of a record which failed is read as a passport of its own
+        if self.broken {
+            self.broken = false;
+            if let Err(e) = self.tokens.skip_record() {
+                return Some(Err(e));
+            }
+        }
+        let mut pairs: IndexMap<String, String> = IndexMap::new();
+        let mut line = None;
+        let mut fields = 0;
+        loop {
+            match self.tokens.next() {
+                Some(Ok(Token::Pair(p))) => {
+                    if self.strict_keys && pairs.contains_key(&p.key) {
+                        return self.error(Error::DuplicateKey(p.key));
+                    }
+                    let start = *line.get_or_insert(self.tokens.line());
+                    fields += 1;
+                    if self.max_fields.is_some_and(|max| fields > max) {
+                        return self.error(Error::TooManyFields(start));
+                    }
+                    pairs.insert(p.key, p.value);
+                }
+                Some(Ok(Token::Break)) | None if !pairs.is_empty() => {
+                    return Some(Ok(Passport::new(pairs, line)));
+                }
+                // ignore extra line breaks
+                Some(Ok(Token::Break)) => (),
+                Some(Err(e)) => return self.error(e),
+                None => break,
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+impl<R: BufRead> iter::FusedIterator for Passports<R> {}
+
+pub trait IntoPassports<R: BufRead> {
+    fn into_passports(self) -> Passports<R>;
+}
+
+impl<R: BufRead> IntoPassports<R> for Tokens<R> {
+    fn into_passports(self) -> Passports<R> {
+        Passports::new(self)
+    }
+}
+
+// Reads passports from an async reader. Lines are read asynchronously until the blank line which
+// ends each record, and the record is then parsed with the same tokenizer as the sync readers.
+// A record which fails to parse is yielded as an error and reading carries on with the next one,
+// but an io error ends the stream
+#[cfg(feature = "async")]
+pub fn passport_stream<R>(reader: R) -> impl futures_util::Stream<Item = Result<Passport>>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    use tokio::io::AsyncBufReadExt;
+
+    futures_util::stream::unfold(Some((reader, 0)), |state| async move {
+        let (mut reader, mut line) = state?;
+        loop {
+            let mut record = String::new();
+            // The number of lines read before the first line of the record
+            let mut first_line = line;
+            loop {
+                let mut buf = String::new();
+                match reader.read_line(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(_) => line += 1,
+                    Err(e) => return Some((Err(Error::from(e)), None)),
+                }
+                if !buf.trim().is_empty() {
+                    record.push_str(&buf);
+                } else if record.is_empty() {
+                    first_line = line;
+                } else {
+                    break;
+                }
+            }
+            if record.is_empty() {
+                return None;
+            }
+            // A record made up only of comments holds no passport
+            if let Some(passport) = parse_record(&record, first_line).transpose() {
+                return Some((passport, Some((reader, line))));
+            }
+        }
+    })
+}
+
+#[cfg(feature = "async")]
+fn parse_record(record: &str, first_line: usize) -> Result<Option<Passport>> {
+    let mut chars = CharReader::new(record.as_bytes());
+    chars.line = first_line;
+    chars.into_tokens().into_passports().next().transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash_of(passport: &Passport) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        passport.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn passports_with_the_same_pairs_in_any_order_are_equal() {
+        let a: Passport = "byr:1980 pid:1 ecl:brn".parse().unwrap();
+        let b: Passport = "ecl:brn\nbyr:1980 pid:1".parse().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+        assert_ne!(a, "byr:1980 pid:2 ecl:brn".parse::<Passport>().unwrap());
+        assert_eq!(a.clone(), a);
+    }
+
+    #[test]
+    fn years_must_be_exactly_four_ascii_digits() {
+        let byr = YearValidator::new(BYR, 1920, 2002);
+        assert!(byr.is_valid("1990"));
+        assert!(!byr.is_valid("+1990"));
+        assert!(!byr.is_valid(" 1990"));
+        assert!(!byr.is_valid("1990 "));
+        assert!(!byr.is_valid("01990"));
+        assert_eq!(validate_field(BYR, "+1990"), Some(false));
+    }
+
+    const VALID: &str = "byr:1980 iyr:2015 eyr:2025 hgt:170cm hcl:#123abc ecl:brn pid:000000001";
+
+    fn passport(input: &str) -> Passport {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn unknown_fields_do_not_hide_the_required_fields() {
+        let with_extra = passport(&format!("{} xyz:1", VALID));
+        assert!(with_extra.contains_required_fields());
+        assert!(with_extra.is_valid());
+        let with_cid_and_extra = passport(&format!("{} cid:1 xyz:1", VALID));
+        assert!(with_cid_and_extra.contains_required_fields());
+        assert!(!passport("byr:1980 xyz:1").contains_required_fields());
+    }
+
+    #[test]
+    fn lenient_parsing_skips_the_whole_broken_record() {
+        let (passports, errors) = parse_lenient("byr:1:\nhgt:3\n\npid:1\n");
+        let passports = passports.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(passports, ["pid:1"]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], (1, Error::ColonInValue(..))));
+    }
+
+    #[test]
+    fn lenient_parsing_keeps_the_records_around_a_broken_one() {
+        let input = format!("{}\n\nbyr:19:80 iyr:2015\necl:brn\n\n{}\n", VALID, VALID);
+        let (passports, errors) = parse_lenient(&input);
+        assert_eq!(passports.len(), 2);
+        assert!(passports.iter().all(|p| p.is_valid()));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 3);
+    }
+
+    #[test]
+    fn a_key_split_by_a_newline_skips_only_its_record() {
+        let (passports, errors) = parse_lenient("byr:1 abc\nhgt:1\n\npid:1");
+        assert_eq!(passports, [passport("pid:1")]);
+        assert!(matches!(errors[..], [(1, Error::NewlineInKey(..))]));
+    }
+
+    #[test]
+    fn skipping_a_record_stops_at_a_custom_separator() {
+        let (passports, errors) = Passports::from_reader(&b"a:1:\nb:2\n---\nc:3\n"[..])
+            .with_separator(RecordSeparator::Line("---".to_string()))
+            .collect_lenient();
+        assert_eq!(passports, [passport("c:3")]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn a_repeated_key_is_only_an_error_with_strict_keys() {
+        let input = "hgt:1 hgt:2 byr:3\n\npid:1";
+        let lenient = parse_passports(input).unwrap();
+        assert_eq!(lenient[0].get(HGT), Some("2"));
+
+        let strict = Passports::from_reader(input.as_bytes())
+            .with_strict_keys(true)
+            .collect::<Vec<_>>();
+        assert_eq!(strict.len(), 2);
+        assert!(matches!(&strict[0], Err(Error::DuplicateKey(key)) if key == HGT));
+        assert_eq!(strict[1].as_ref().unwrap(), &passport("pid:1"));
+    }
+
+    #[test]
+    fn a_record_with_too_many_fields_is_an_error() {
+        let passports = Passports::from_reader(&b"a:1 b:2\n\n\na:1 b:2 c:3\nd:4\n\npid:1"[..])
+            .with_max_fields(2)
+            .collect::<Vec<_>>();
+        assert_eq!(passports.len(), 3);
+        assert_eq!(passports[0].as_ref().unwrap(), &passport("a:1 b:2"));
+        assert!(matches!(passports[1], Err(Error::TooManyFields(4))));
+        assert_eq!(passports[2].as_ref().unwrap(), &passport("pid:1"));
+    }
+
+    #[test]
+    fn fields_which_are_not_required_are_only_checked_when_present() {
+        let config = ValidationConfig {
+            required: vec![BYR.to_string()],
+            ..ValidationConfig::default()
+        };
+        let validator = Validator::from(&config);
+        let byr_only = passport("byr:1980");
+        assert!(validator.has_required_fields(&byr_only));
+        assert_eq!(validator.status(&byr_only), PassportStatus::Valid);
+        let report = validator.validate(&byr_only);
+        assert_eq!(report.iter().collect::<Vec<_>>(), [(BYR, &FieldStatus::Valid)]);
+
+        let bad_pid = passport("byr:1980 pid:12");
+        assert_eq!(validator.status(&bad_pid), PassportStatus::RequiredButInvalid);
+        assert_eq!(validator.status(&passport("pid:000000001")), PassportStatus::Missing);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn rules_loaded_from_a_file_apply() {
+        let path = std::env::temp_dir().join(format!("adv2020_4_rules_{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"byr": [1900, 2002], "required": ["byr", "pid"]}"#).unwrap();
+        let config = ValidationConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.byr, (1900, 2002));
+        let old = passport(&VALID.replace("byr:1980", "byr:1910"));
+        assert!(!old.is_valid());
+        assert!(old.is_valid_with(&config));
+        assert_eq!(passport("byr:1910 pid:000000001").status_with(&config), PassportStatus::Valid);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn unknown_settings_in_a_rules_file_are_an_error() {
+        let path = std::env::temp_dir().join(format!("adv2020_4_bad_rules_{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"byr_range": [1900, 2002]}"#).unwrap();
+        let config = ValidationConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(config, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn a_single_field_is_checked_against_its_rules() {
+        assert_eq!(validate_field(HGT, "180cm"), Some(true));
+        assert_eq!(validate_field(HGT, "194cm"), Some(false));
+        assert_eq!(validate_field(HGT, "190"), Some(false));
+        assert_eq!(validate_field(CID, "anything"), None);
+        assert_eq!(validate_field("xyz", "whatever"), None);
+    }
+
+    #[test]
+    fn standalone_year_validators_include_their_bounds() {
+        type Check = fn(&str) -> bool;
+        let cases: [(Check, u32, u32); 3] = [
+            (validators::byr, 1920, 2002),
+            (validators::iyr, 2010, 2020),
+            (validators::eyr, 2020, 2030),
+        ];
+        for (check, from, to) in cases.iter() {
+            assert!(!check(&(from - 1).to_string()));
+            assert!(check(&from.to_string()));
+            assert!(check(&to.to_string()));
+            assert!(!check(&(to + 1).to_string()));
+            assert!(!check(&format!("+{}", from)));
+        }
+    }
+
+    #[test]
+    fn standalone_height_validator_includes_its_bounds() {
+        for (height, valid) in [
+            ("149cm", false),
+            ("150cm", true),
+            ("193cm", true),
+            ("194cm", false),
+            ("58in", false),
+            ("59in", true),
+            ("76in", true),
+            ("77in", false),
+            ("170", false),
+            ("0150cm", true),
+        ]
+        .iter()
+        {
+            assert_eq!(validators::hgt(height), *valid, "{}", height);
+        }
+        assert_eq!(validators::parse_height("0150cm", true), None);
+        assert_eq!(validators::parse_feet_and_inches("5ft11in"), Some(71));
+        assert_eq!(validators::parse_feet_and_inches("5ft12in"), None);
+    }
+
+    #[test]
+    fn standalone_pattern_validators() {
+        assert!(validators::hcl("#0a9f00"));
+        assert!(!validators::hcl("#0a9f0"));
+        assert!(!validators::hcl("#0a9f000"));
+        assert!(!validators::hcl("0a9f000"));
+        assert!(!validators::hcl("#0A9F00"));
+        assert!(validators::ecl("amb") && validators::ecl("oth"));
+        assert!(!validators::ecl("wat") && !validators::ecl("AMB"));
+        assert!(validators::pid("000000000"));
+        assert!(!validators::pid("00000000"));
+        assert!(!validators::pid("0000000000"));
+    }
+
+    fn read_chars<R: BufRead>(chars: CharReader<R>) -> String {
+        chars.collect::<Result<String>>().unwrap()
+    }
+
+    #[test]
+    fn the_reversed_reader_handles_multibyte_characters() {
+        let input = "cid:Münche\u{1F600}n ecl:brn\nhcl:é\n";
+        assert_eq!(read_chars(CharReader::reversed(input.as_bytes())), input);
+        let passport = CharReader::reversed(input.as_bytes())
+            .into_tokens()
+            .into_passports()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(passport.get(CID), Some("Münche\u{1F600}n"));
+        assert_eq!(passport.get(HCL), Some("é"));
+    }
+
+    #[test]
+    fn count_matches_the_separate_filters() {
+        let passports = generate_passports(1_000, 0.5, 18);
+        let counts = DEFAULT_VALIDATOR.count(&passports);
+        assert_eq!(counts, (num_with_required_fields(&passports), num_valid(&passports)));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_count_matches_the_sequential_count() {
+        let passports = generate_passports(100_000, 0.5, 18);
+        let validator = Validator::from(&ValidationConfig::strict());
+        assert_eq!(DEFAULT_VALIDATOR.par_count(&passports), DEFAULT_VALIDATOR.count(&passports));
+        assert_eq!(validator.par_count(&passports), validator.count(&passports));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn passports_round_trip_through_json() {
+        let original = passport("pid:000000001 byr:1980\ncid:12 ecl:brn");
+        let json = serde_json::to_string(&original).unwrap();
+        assert_eq!(json, r#"{"pid":"000000001","byr":"1980","cid":"12","ecl":"brn"}"#);
+        let parsed = serde_json::from_str::<Passport>(&json).unwrap();
+        assert_eq!(parsed, original);
+        assert_eq!(parsed.to_string(), original.to_string());
+        assert!(serde_json::from_str::<Passport>(r#"{"": "1"}"#).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn passport_stream_reads_an_async_reader() {
+        use futures_util::StreamExt;
+
+        let input: &[u8] = b"byr:1980 pid:1\n\n\nbyr:1:\n\necl:brn\nhgt:170cm\n";
+        let results: Vec<Result<Passport>> = passport_stream(input).collect().await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &passport("byr:1980 pid:1"));
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), &passport("ecl:brn hgt:170cm"));
+    }
+
+    #[test]
+    fn forward_and_reversed_readers_emit_the_same_characters() {
+        let input = "ecl:brn pid:000000001\r\nhcl:#123abc cid:Münche\u{1F600}n\n\n\
+                     hgt:170cm\r\n\r\nbyr:1980 iyr:é\u{1F600}";
+        let forward: Vec<char> = CharReader::new(input.as_bytes()).map(Result::unwrap).collect();
+        let reversed: Vec<char> =
+            CharReader::reversed(input.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(forward, reversed);
+        assert_eq!(forward.iter().collect::<String>(), input.replace("\r\n", "\n"));
+    }
+
+    #[test]
+    fn crlf_line_endings_parse_and_validate() {
+        let input = "byr:1980 iyr:2015 eyr:2025\r\nhgt:170cm hcl:#123abc\r\necl:brn pid:000000001\r\n\
+                     \r\nbyr:1980 pid:000000002\r\n";
+        let passports = parse_passports(input).unwrap();
+        assert_eq!(passports.len(), 2);
+        assert_eq!(passports[0], passport(VALID));
+        assert!(passports[0].is_valid());
+        assert_eq!(passports[1].get("pid"), Some("000000002"));
+    }
+
+    #[test]
+    fn the_report_names_every_failing_field() {
+        let report = passport("byr:1900 iyr:2015 eyr:2025 hgt:170 hcl:123abc ecl:brn").validate();
+        assert!(!report.is_valid());
+        assert_eq!(report.field("byr"), Some(&FieldStatus::Invalid("1900".to_string())));
+        assert_eq!(report.field("hgt"), Some(&FieldStatus::Invalid("170".to_string())));
+        assert_eq!(report.field("hcl"), Some(&FieldStatus::Invalid("123abc".to_string())));
+        assert_eq!(report.field("pid"), Some(&FieldStatus::Missing));
+        assert_eq!(report.field("iyr"), Some(&FieldStatus::Valid));
+        assert_eq!(report.field("ecl"), Some(&FieldStatus::Valid));
+        assert!(passport(VALID).validate().is_valid());
+    }
+
+    #[test]
+    fn a_single_passport_parses_from_a_string() {
+        let parsed: Passport = "byr:1980 iyr:2015\neyr:2025 hgt:170cm".parse().unwrap();
+        assert_eq!(parsed.get("byr"), Some("1980"));
+        assert_eq!(parsed.get("hgt"), Some("170cm"));
+        assert_eq!(parsed.field_count(), 4);
+        assert!(matches!("byr:19:80".parse::<Passport>(), Err(Error::ColonInValue(..))));
+        assert!(matches!("".parse::<Passport>(), Err(Error::NoPassport)));
+        assert!(matches!("byr:1980\n\npid:1".parse::<Passport>(), Err(Error::MultiplePassports)));
+    }
+
+    #[test]
+    fn io_errors_are_the_source_of_the_error() {
+        let e = Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated"));
+        let dyn_error: &dyn error::Error = &e;
+        let source = dyn_error.source().unwrap();
+        assert_eq!(source.to_string(), "truncated");
+        assert!(source.downcast_ref::<io::Error>().is_some());
+        assert!((&Error::EmptyKey as &dyn error::Error).source().is_none());
+        let boxed: Box<dyn error::Error> = Box::new(Error::EmptyKey);
+        assert_eq!(boxed.to_string(), Error::EmptyKey.to_string());
+    }
+
+    #[test]
+    fn each_passport_records_the_line_it_starts_on() {
+        let input = "byr:1980\npid:1\n\n\necl:brn\n\nhgt:170cm\niyr:2015\n";
+        let passports = parse_passports(input).unwrap();
+        let lines: Vec<Option<usize>> = passports.iter().map(Passport::line).collect();
+        assert_eq!(lines, vec![Some(1), Some(5), Some(7)]);
+        assert_eq!(passport("byr:1980").line(), Some(1));
+    }
+
+    #[test]
+    fn a_custom_validator_can_widen_the_byr_range() {
+        let old = passport(&VALID.replace("byr:1980", "byr:1900"));
+        assert!(!old.is_valid());
+        let validator = Validator::default().with(YearValidator::new(BYR, 1900, 2002));
+        assert!(validator.is_valid(&old));
+        assert!(validator.is_valid(&passport(VALID)));
+        assert!(!validator.is_valid(&passport(&VALID.replace("byr:1980", "byr:1899"))));
+        assert!(!validator.is_valid(&passport(&VALID.replace("hgt:170cm", "hgt:170"))));
+    }
+
+    #[test]
+    fn fields_can_be_read_back() {
+        let parsed = passport("byr:1980 pid:000000001\necl:brn");
+        assert_eq!(parsed.get("byr"), Some("1980"));
+        assert_eq!(parsed.get("hgt"), None);
+        let fields: Vec<(&str, &str)> = parsed.iter().collect();
+        assert_eq!(fields, vec![("byr", "1980"), ("pid", "000000001"), ("ecl", "brn")]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_compressed_input_parses_to_the_same_passports() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let input = format!("{}\n\nbyr:1980 pid:1\n\necl:brn\n", VALID);
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let chars = CharReader::from_read(flate2::read::GzDecoder::new(&compressed[..]));
+        let passports = chars.into_tokens().into_passports().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(passports, parse_passports(&input).unwrap());
+
+        let path = std::env::temp_dir().join(format!("adv2020_4-{}.gz", std::process::id()));
+        std::fs::write(&path, &compressed).unwrap();
+        let from_file = open_char_reader_gz(&path).unwrap().into_tokens().into_passports().count();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file, 3);
+    }
+
+    #[test]
+    fn uppercase_eye_colors_are_only_valid_when_opted_in() {
+        let lower = passport(VALID);
+        let upper = passport(&VALID.replace("ecl:brn", "ecl:BRN"));
+        let validator = Validator::default().case_insensitive_ecl();
+        assert!(lower.is_valid());
+        assert!(!upper.is_valid());
+        assert!(validator.is_valid(&lower));
+        assert!(validator.is_valid(&upper));
+        assert!(!validator.is_valid(&passport(&VALID.replace("ecl:brn", "ecl:XYZ"))));
+    }
+
+    #[test]
+    fn field_failures_count_each_failing_field() {
+        let passports = vec![
+            passport(VALID),
+            passport(&VALID.replace("byr:1980", "byr:2003").replace("hgt:170cm", "hgt:170")),
+            passport(&VALID.replace("hgt:170cm", "hgt:200cm").replace(" pid:000000001", "")),
+        ];
+        let failures = field_failures(&passports);
+        let mut counts: Vec<(&str, usize)> =
+            failures.iter().map(|(field, count)| (field.as_str(), *count)).collect();
+        counts.sort();
+        assert_eq!(counts, vec![("byr", 1), ("hgt", 2), ("pid", 1)]);
+    }
+
+    #[test]
+    fn peeking_does_not_consume_the_token() {
+        let pair = |key: &str, value: &str| {
+            Token::Pair(Pair::new(key.to_string(), value.to_string()))
+        };
+        let mut tokens = CharReader::new("byr:1980\n\npid:1".as_bytes()).into_tokens();
+        assert_eq!(tokens.peek().unwrap().as_ref().unwrap(), &pair("byr", "1980"));
+        assert_eq!(tokens.peek().unwrap().as_ref().unwrap(), &pair("byr", "1980"));
+        assert_eq!(tokens.next().unwrap().unwrap(), pair("byr", "1980"));
+        assert_eq!(tokens.next().unwrap().unwrap(), Token::Break);
+        assert_eq!(tokens.next().unwrap().unwrap(), pair("pid", "1"));
+        assert!(tokens.peek().is_none());
+        assert!(tokens.peek().is_none());
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn streaming_counts_match_collecting_the_passports() {
+        let input = std::fs::read_to_string("input.txt").unwrap();
+        let passports = parse_passports(&input).unwrap();
+        let (total, required, valid) = count_passports(input.as_bytes()).unwrap();
+        assert_eq!(total, passports.len());
+        assert_eq!(required, num_with_required_fields(&passports));
+        assert_eq!(valid, num_valid(&passports));
+        assert_eq!((total, required, valid), (287, 219, 127));
+    }
+
+    #[test]
+    fn year_order_is_only_checked_in_the_strict_mode() {
+        // eyr 2012 is outside the default range, so the range is widened to leave only the order
+        let mut config = ValidationConfig { eyr: (2010, 2030), ..ValidationConfig::default() };
+        let expired = passport(&VALID.replace("byr:1980", "byr:1990").replace("eyr:2025", "eyr:2012"));
+        assert!(Validator::from(&config).is_valid(&expired));
+        config.check_year_order = true;
+        assert!(!Validator::from(&config).is_valid(&expired));
+        assert!(Validator::from(&config).is_valid(&passport(VALID)));
+
+        config.byr = (1920, 2020);
+        let born = passport(&VALID.replace("byr:1980", "byr:2016"));
+        assert!(!Validator::from(&config).is_valid(&born));
+        assert!(Validator::from(&config).with_year_order_check(false).is_valid(&born));
+    }
+
+    #[test]
+    fn custom_ranges_replace_the_default_ranges() {
+        let config = ValidationConfig {
+            byr: (1900, 1950),
+            iyr: (2000, 2005),
+            eyr: (2040, 2050),
+            hgt_cm: (100, 120),
+            hgt_in: (40, 45),
+            ..ValidationConfig::default()
+        };
+        let custom = "byr:1900 iyr:2005 eyr:2040 hgt:120cm hcl:#123abc ecl:brn pid:000000001";
+        assert!(passport(custom).is_valid_with(&config));
+        assert!(!passport(custom).is_valid());
+        assert!(!passport(VALID).is_valid_with(&config));
+        assert!(passport(&custom.replace("hgt:120cm", "hgt:40in")).is_valid_with(&config));
+        assert!(!passport(&custom.replace("byr:1900", "byr:1951")).is_valid_with(&config));
+    }
+
+    #[test]
+    fn a_colon_in_a_value_reports_its_position() {
+        let input = "byr:1980 pid:1\necl:brn hgt:17:0cm\n";
+        let e = parse_passports(input).unwrap_err();
+        match &e {
+            Error::ColonInValue(position, key, value) => {
+                assert_eq!(*position, Position { line: 2, column: 15 });
+                assert_eq!((key.as_str(), value.as_str()), ("hgt", "17"));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(e.to_string().ends_with("at line 2, column 15"), "{}", e);
+    }
+
+    #[test]
+    fn fields_keep_the_order_they_were_read_in() {
+        let keys = |input| {
+            passport(input).iter().map(|(key, _)| key.to_string()).collect::<Vec<String>>()
+        };
+        assert_eq!(keys("byr:1 iyr:2 eyr:3"), vec!["byr", "iyr", "eyr"]);
+        assert_eq!(keys("eyr:3\niyr:2 byr:1"), vec!["eyr", "iyr", "byr"]);
+    }
+
+    #[test]
+    fn tabs_separate_fields() {
+        let tabbed = passport(&VALID.replace(' ', "\t"));
+        assert_eq!(tabbed, passport(VALID));
+        assert!(tabbed.is_valid());
+        let mixed = passport("byr:1980\t \tiyr:2015 \t\n\teyr:2025");
+        assert_eq!(mixed.field_count(), 3);
+        assert_eq!(mixed.get("iyr"), Some("2015"));
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic() {
+        let mut rng = Rng(30);
+        // Bytes which exercise the parser are more likely than the rest
+        let interesting = b": \n\r\t#\xc3\xa9\xff";
+        for _ in 0..2000 {
+            let len = rng.range(0, 64) as usize;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| match rng.range(0, 2) {
+                    0 => interesting[rng.range(0, interesting.len() as u64 - 1) as usize],
+                    1 => b'a' + rng.range(0, 25) as u8,
+                    _ => rng.range(0, 255) as u8,
+                })
+                .collect();
+            let result = std::panic::catch_unwind(|| {
+                Passports::from_reader(io::Cursor::new(&bytes)).for_each(drop);
+                let chars = CharReader::reversed(io::Cursor::new(&bytes));
+                chars.into_tokens().into_passports().for_each(drop);
+                Passports::from_reader(io::Cursor::new(&bytes)).collect_lenient()
+            });
+            assert!(result.is_ok(), "panicked on {:?}", bytes);
+        }
+        let invalid_utf8 = Passports::from_reader(&b"byr:\xff\xfe"[..]).next().unwrap();
+        assert!(matches!(invalid_utf8, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn the_builder_makes_a_valid_passport() {
+        let built = Passport::builder()
+            .byr("1980")
+            .iyr("2015")
+            .eyr("2025")
+            .hgt("170cm")
+            .hcl("#123abc")
+            .ecl("brn")
+            .pid("000000001")
+            .build();
+        assert!(built.is_valid());
+        assert_eq!(built, passport(VALID));
+        let extra = Passport::builder().byr("1980").field("xyz", "1").build();
+        assert_eq!(extra.get("xyz"), Some("1"));
+        assert!(!extra.is_valid());
+    }
+
+    #[test]
+    fn passports_display_as_their_pairs_in_order() {
+        assert_eq!(passport("byr:1980 pid:1\necl:brn").to_string(), "byr:1980 pid:1 ecl:brn");
+        assert_eq!(format!("[{}]", passport("cid:12")), "[cid:12]");
+    }
+
+    #[test]
+    fn feet_and_inches_are_only_accepted_when_enabled() {
+        let config = ValidationConfig { hgt_feet_and_inches: true, ..ValidationConfig::default() };
+        let validator = Validator::from(&config);
+        let hgt = |value: &str| {
+            let record = passport(&VALID.replace("hgt:170cm", &format!("hgt:{}", value)));
+            (record.is_valid(), validator.is_valid(&record))
+        };
+        assert_eq!(hgt("5ft10in"), (false, true));
+        assert_eq!(hgt("6ft5in"), (false, false));
+        assert_eq!(hgt("5ft"), (false, true));
+        assert_eq!(hgt("5ft0in"), (false, true));
+        assert_eq!(hgt("4ft"), (false, false));
+        assert_eq!(hgt("5ft12in"), (false, false));
+        assert_eq!(hgt("70in"), (true, true));
+        assert_eq!(hgt("77in"), (false, false));
+        assert_eq!(hgt("170cm"), (true, true));
+        assert_eq!(hgt("194cm"), (false, false));
+    }
+
+    #[test]
+    fn extra_blank_lines_do_not_add_to_the_total() {
+        let input = format!("\n\n{}\n\n\n\nbyr:1980\n\n \n\npid:1\n\n\n\n", VALID);
+        assert_eq!(count_passports(input.as_bytes()).unwrap(), (3, 1, 1));
+        assert_eq!(count_passports(&b"\n\n\n"[..]).unwrap(), (0, 0, 0));
+    }
+
+    #[test]
+    fn extra_patterns_check_present_fields() {
+        let mut config = ValidationConfig::default();
+        config.patterns.insert(CID.to_string(), Regex::new(r"^\d{3}$").unwrap());
+        assert!(passport(&format!("{} cid:123", VALID)).is_valid_with(&config));
+        assert!(!passport(&format!("{} cid:12", VALID)).is_valid_with(&config));
+        assert!(!passport(&format!("{} cid:1234", VALID)).is_valid_with(&config));
+        assert!(passport(VALID).is_valid_with(&config));
+        // The built in rules still apply alongside the pattern
+        assert!(!passport(&format!("{} cid:123", VALID.replace("byr:1980", "byr:1900")))
+            .is_valid_with(&config));
+    }
+
+    #[test]
+    fn a_missing_cid_only_fails_the_strict_passport_mode() {
+        let north_pole = passport(VALID);
+        let genuine = passport(&format!("{} cid:12", VALID));
+        assert!(north_pole.contains_required_fields());
+        assert!(!north_pole.contains_strict_passport_fields());
+        assert!(genuine.contains_strict_passport_fields());
+        assert!(north_pole.is_valid());
+        let strict = ValidationConfig::default().into_strict();
+        assert_eq!(strict.required_fields_policy, RequiredFieldsPolicy::RequireCid);
+        assert_eq!(north_pole.status_with(&strict), PassportStatus::Missing);
+        assert_eq!(genuine.status_with(&strict), PassportStatus::Valid);
+    }
+
+    #[test]
+    fn multibyte_values_come_out_of_both_readers_unchanged() {
+        // é is two bytes and 😀 is four
+        let value = "Münch\u{e9}n\u{1F600}";
+        let input = format!("cid:{} ecl:brn\n", value);
+        let pairs = |chars: CharReader<&[u8]>| {
+            chars.into_tokens().pairs().collect::<Result<Vec<(String, String)>>>().unwrap()
+        };
+        let expected = vec![
+            ("cid".to_string(), value.to_string()),
+            ("ecl".to_string(), "brn".to_string()),
+        ];
+        assert_eq!(pairs(CharReader::new(input.as_bytes())), expected);
+        assert_eq!(pairs(CharReader::reversed(input.as_bytes())), expected);
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        let input = format!(
+            "# sample data\n{}\n  # an indented comment\n\nbyr:1980\n#pid:2\nhcl:#123abc\n",
+            VALID.replacen(' ', "\n# between fields\n", 1)
+        );
+        let passports = parse_passports(&input).unwrap();
+        assert_eq!(passports.len(), 2);
+        assert_eq!(passports[0], passport(VALID));
+        assert_eq!(passports[1].get("pid"), None);
+        // A # inside a value is an ordinary character
+        assert_eq!(passports[1].get("hcl"), Some("#123abc"));
+    }
+
+    #[test]
+    fn status_classifies_each_kind_of_record() {
+        assert_eq!(passport("byr:1980 pid:000000001").status(), PassportStatus::Missing);
+        let invalid = passport(&VALID.replace("pid:000000001", "pid:1"));
+        assert_eq!(invalid.status(), PassportStatus::RequiredButInvalid);
+        assert_eq!(passport(VALID).status(), PassportStatus::Valid);
+    }
+
+    #[test]
+    fn a_string_with_two_records_parses() {
+        let passports = parse_passports("byr:1980 pid:1\n\necl:brn\nhgt:170cm\n").unwrap();
+        assert_eq!(passports, vec![passport("byr:1980 pid:1"), passport("ecl:brn hgt:170cm")]);
+        assert_eq!(parse_passports("").unwrap(), vec![]);
+        assert!(parse_passports("byr:1980\n\npid:1:2\n").is_err());
+    }
+
+    #[test]
+    fn the_adapters_match_the_known_counts() {
+        let input = std::fs::read_to_string("input.txt").unwrap();
+        assert_eq!(Passports::from_reader(input.as_bytes()).valid().count(), 127);
+        assert_eq!(Passports::from_reader(input.as_bytes()).with_required_fields().count(), 219);
+        // Records are pulled one at a time, so the first passport comes out ahead of the broken one
+        let first = format!("{}\n\nbyr:1:2\n", VALID);
+        let mut valid = Passports::from_reader(first.as_bytes()).valid();
+        assert_eq!(valid.next().unwrap().unwrap(), passport(VALID));
+        assert!(valid.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn a_line_over_the_limit_is_an_error() {
+        let input = "byr:1980\npid:0123456789\n";
+        let chars = CharReader::new(input.as_bytes()).with_max_line_len(8);
+        let result = chars.collect::<Result<String>>();
+        // The error holds the line which was too long
+        assert!(matches!(result, Err(Error::LineTooLong(2))), "{:?}", result);
+        let passports = CharReader::new(&b"byr:1980\n"[..]).with_max_line_len(8).into_tokens();
+        assert_eq!(passports.into_passports().count(), 1);
+        // A \r\n ending does not count towards the limit either
+        let crlf = CharReader::new(&b"abcd\r\nefgh\n"[..]).with_max_line_len(4);
+        assert_eq!(read_chars(crlf), "abcd\nefgh\n");
+        let crlf = CharReader::new(&b"abcd\r\nabcde\r\n"[..]).with_max_line_len(4);
+        assert!(matches!(crlf.collect::<Result<String>>(), Err(Error::LineTooLong(2))));
+        let long = vec![b'a'; 100_000];
+        let mut chars = CharReader::new(&long[..]).with_max_line_len(16);
+        assert!(matches!(chars.next(), Some(Err(Error::LineTooLong(1)))));
+    }
+
+    #[test]
+    fn records_can_be_separated_by_a_marker_line() {
+        let input = "byr:1980 pid:1\n\niyr:2015\n---\necl:brn\n---\n---\nhgt:170cm\n";
+        let passports = Passports::from_reader(input.as_bytes())
+            .with_separator(RecordSeparator::Line("---".to_string()))
+            .collect::<Result<Vec<Passport>>>()
+            .unwrap();
+        let expected = vec![
+            passport("byr:1980 pid:1 iyr:2015"),
+            passport("ecl:brn"),
+            passport("hgt:170cm"),
+        ];
+        assert_eq!(passports, expected);
+    }
+
+    #[test]
+    fn num_with_required_fields_counts_hand_built_passports() {
+        let passports = vec![
+            Passport::builder().byr("1980").pid("1").build(),
+            passport(VALID),
+            passport(&VALID.replace("hgt:170cm", "hgt:170")),
+            passport(&format!("{} cid:1", VALID)),
+        ];
+        assert_eq!(num_with_required_fields(&passports), 3);
+        assert_eq!(num_with_required_fields(&[]), 0);
+    }
+
+    #[test]
+    fn num_valid_counts_hand_built_passports() {
+        let passports = vec![
+            Passport::builder().byr("1980").pid("1").build(),
+            passport(VALID),
+            passport(&VALID.replace("hgt:170cm", "hgt:170")),
+            passport(&format!("{} cid:1", VALID)),
+        ];
+        assert_eq!(num_valid(&passports), 2);
+        assert_eq!(num_valid(&passports[..1]), 0);
+    }
+
+    #[test]
+    fn numeric_pids_are_only_padded_when_opted_in() {
+        let validator = Validator::default().numeric_pid();
+        let pid = |value: &str| {
+            let record = passport(&VALID.replace("pid:000000001", &format!("pid:{}", value)));
+            (record.is_valid(), validator.is_valid(&record))
+        };
+        assert_eq!(pid("012345678"), (true, true));
+        assert_eq!(pid("12345678"), (false, true));
+        assert_eq!(pid("0123456789"), (false, false));
+        assert_eq!(pid("1234a678"), (false, false));
+        assert_eq!(validator.validate_field(PID, "1"), Some(true));
+    }
+
+    #[test]
+    fn pair_errors_carry_the_partial_text() {
+        match parse_passports("byr:1980 hgt\n") {
+            Err(e @ Error::NewlineInKey(..)) => assert!(e.to_string().contains("\"hgt\""), "{}", e),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match parse_passports("byr:1980 ecl") {
+            Err(Error::EofInKey(_, key)) => assert_eq!(key, "ecl"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match parse_passports("pid:01:2") {
+            Err(e @ Error::ColonInValue(..)) => {
+                let message = e.to_string();
+                assert!(message.contains("\"pid\"") && message.contains("\"01\""), "{}", message);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn merging_two_halves_gives_a_valid_passport() {
+        let mut first = passport("byr:1980 iyr:2015 eyr:2025 hgt:150");
+        let second = passport("hgt:170cm hcl:#123abc ecl:brn pid:000000001");
+        assert!(!first.contains_required_fields());
+
+        let mut kept = first.clone();
+        kept.merge(second.clone(), MergePolicy::KeepExisting);
+        assert_eq!(kept.get("hgt"), Some("150"));
+        assert!(!kept.is_valid());
+
+        first.merge(second, MergePolicy::Replace);
+        assert_eq!(first.get("hgt"), Some("170cm"));
+        assert_eq!(first.field_count(), 7);
+        assert!(first.is_valid());
+    }
+
+    #[test]
+    fn raw_text_keeps_the_whitespace_of_each_record() {
+        let first = "byr:1980   pid:1\t\n  ecl:brn \n";
+        let second = "hgt:170cm\n";
+        let input = format!("\n{}\n\n{}", first, second);
+        let records = Passports::from_reader(input.as_bytes())
+            .into_iter_with_raw()
+            .collect::<Result<Vec<(Passport, String)>>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], (passport("byr:1980 pid:1 ecl:brn"), first.to_string()));
+        assert_eq!(records[1], (passport("hgt:170cm"), second.to_string()));
+    }
+
+    #[test]
+    fn leading_zeros_in_a_height_only_fail_the_strict_amount() {
+        let lenient = HeightValidator::new(HGT, validators::HGT_CM_RANGE, validators::HGT_IN_RANGE);
+        let strict = HeightValidator::new(HGT, validators::HGT_CM_RANGE, validators::HGT_IN_RANGE)
+            .with_strict_amount(true);
+        assert!(lenient.is_valid("0150cm"));
+        assert!(!strict.is_valid("0150cm"));
+        assert!(!strict.is_valid("+150cm"));
+        assert!(!strict.is_valid("060in"));
+        for value in ["150cm", "60in"] {
+            assert!(lenient.is_valid(value) && strict.is_valid(value), "{}", value);
+        }
+        let config = ValidationConfig { hgt_strict_amount: true, ..ValidationConfig::default() };
+        assert!(passport(&VALID.replace("170cm", "0170cm")).is_valid());
+        assert!(!passport(&VALID.replace("170cm", "0170cm")).is_valid_with(&config));
+    }
+
+    #[test]
+    fn sorting_puts_passports_without_the_field_last() {
+        let mut passports = vec![
+            passport("pid:3 byr:1990"),
+            passport("byr:1970"),
+            passport("pid:1 byr:2000"),
+            passport("pid:2"),
+        ];
+        sort_passports(&mut passports, SortKey::Pid);
+        let pids: Vec<Option<&str>> = passports.iter().map(|p| p.get("pid")).collect();
+        assert_eq!(pids, vec![Some("1"), Some("2"), Some("3"), None]);
+        sort_passports(&mut passports, SortKey::Byr);
+        let byrs: Vec<Option<&str>> = passports.iter().map(|p| p.get("byr")).collect();
+        assert_eq!(byrs, vec![Some("1970"), Some("1990"), Some("2000"), None]);
+    }
+
+    #[test]
+    fn values_split_a_field_into_its_parts() {
+        let parsed = passport("hobbies:ski;surf byr:1980 tags:a,b");
+        assert_eq!(parsed.values("hobbies").collect::<Vec<&str>>(), vec!["ski", "surf"]);
+        assert_eq!(parsed.values("byr").collect::<Vec<&str>>(), vec!["1980"]);
+        assert_eq!(parsed.values("pid").count(), 0);
+        assert_eq!(parsed.values_split_by("tags", ',').collect::<Vec<&str>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn passports_stay_finished_after_the_end() {
+        let mut passports = Passports::from_reader("byr:1980\n\npid:1\n".as_bytes());
+        assert!(passports.next().is_some());
+        assert!(passports.next().is_some());
+        for _ in 0..5 {
+            assert!(passports.next().is_none());
+        }
+        let mut empty = Passports::from_reader(&b""[..]);
+        assert!(empty.next().is_none());
+        assert!(empty.next().is_none());
+    }
+
+    #[test]
+    fn check_parse_counts_records_or_reports_the_first_error() {
+        let clean = format!("{}\n\n\nbyr:1980\n\npid:1\n\n", VALID);
+        assert_eq!(check_parse(clean.as_bytes()).unwrap(), 3);
+        assert_eq!(check_parse(&b""[..]).unwrap(), 0);
+        let malformed = "byr:1980\n\nhgt:1:70\n\npid\n";
+        assert!(matches!(check_parse(malformed.as_bytes()), Err(Error::ColonInValue(..))));
+    }
+
+    #[test]
+    fn trimming_removes_whitespace_around_keys_and_values() {
+        let pairs = |input: &str, trim: bool| {
+            CharReader::new(input.as_bytes())
+                .into_tokens()
+                .with_trimming(trim)
+                .pairs()
+                .collect::<Result<Vec<(String, String)>>>()
+        };
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(pairs("byr : 1980 \n", true).unwrap(), vec![pair("byr", "1980")]);
+        assert_eq!(
+            pairs("byr : 1980 pid:1\n", true).unwrap(),
+            vec![pair("byr", "1980"), pair("pid", "1")]
+        );
+        assert_eq!(pairs("byr: \n", true).unwrap(), vec![pair("byr", "")]);
+        assert!(matches!(pairs("byr: ecl:brn\n", true), Err(Error::ColonInValue(..))));
+        assert_ne!(pairs("byr : 1980 \n", false).ok(), Some(vec![pair("byr", "1980")]));
+    }
+
+    #[test]
+    fn missing_required_fields_are_listed_in_order() {
+        let record = passport("ecl:brn eyr:2025 hcl:#123abc iyr:2015 byr:1980 cid:1");
+        assert_eq!(record.missing_required_fields(), vec!["hgt", "pid"]);
+        assert!(passport(VALID).missing_required_fields().is_empty());
+    }
+
+    #[test]
+    fn readers_without_a_buffer_are_wrapped() {
+        // Only implements Read, so it must be buffered by from_read
+        struct Unbuffered(io::Cursor<Vec<u8>>);
+
+        impl Read for Unbuffered {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        let input = format!("{}\n\nbyr:1980\n", VALID).into_bytes();
+        let forward = CharReader::from_read(Unbuffered(io::Cursor::new(input.clone())));
+        assert_eq!(read_chars(forward), String::from_utf8(input.clone()).unwrap());
+        let reversed = CharReader::reversed_from_read(Unbuffered(io::Cursor::new(input)));
+        assert_eq!(reversed.into_tokens().into_passports().count(), 2);
+        let file = File::open("input.txt").unwrap();
+        assert_eq!(CharReader::from_read(file).into_tokens().into_passports().count(), 287);
+    }
+
+    #[test]
+    fn uppercase_hair_colors_are_only_valid_when_opted_in() {
+        let validator = Validator::default().case_insensitive_hcl();
+        let hcl = |value: &str| {
+            let record = passport(&VALID.replace("hcl:#123abc", &format!("hcl:{}", value)));
+            (record.is_valid(), validator.is_valid(&record))
+        };
+        assert_eq!(hcl("#abc123"), (true, true));
+        assert_eq!(hcl("#ABC123"), (false, true));
+        assert_eq!(hcl("#xyz123"), (false, false));
+        assert_eq!(hcl("#XYZ123"), (false, false));
+    }
+
+    #[test]
+    fn count_by_status_separates_each_kind_of_record() {
+        let input = format!("byr:1980\n\n{}\n\n{}\n", VALID.replace("ecl:brn", "ecl:xxx"), VALID);
+        let counts = Passports::from_reader(input.as_bytes()).count_by_status().unwrap();
+        let expected = StatusCounts {
+            total: 3,
+            missing_fields: 1,
+            required_but_invalid: 1,
+            valid: 1,
+        };
+        assert_eq!(counts, expected);
+        let sum = counts.missing_fields + counts.required_but_invalid + counts.valid;
+        assert_eq!(sum, counts.total);
+        assert!(Passports::from_reader(&b"byr:1:2\n"[..]).count_by_status().is_err());
+    }
+
+    #[test]
+    fn blank_only_input_has_no_passports() {
+        for input in ["\n", "\n\n\n", "\n \n\t\n", "\n\n  ", "\r\n\r\n"] {
+            assert_eq!(parse_passports(input).unwrap(), vec![], "{:?}", input);
+            assert_eq!(count_passports(input.as_bytes()).unwrap(), (0, 0, 0), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn a_missing_file_is_reported_with_its_path() {
+        let e = open_char_reader("does_not_exist").err().unwrap();
+        assert!(matches!(&e, Error::Open(path, _) if path == Path::new("does_not_exist")));
+        let message = e.to_string();
+        assert!(message.contains("'does_not_exist'"), "{}", message);
+        assert!(error::Error::source(&e).is_some());
+    }
+
+    #[test]
+    fn streamed_reports_match_the_eager_reports() {
+        let fields = |report: &ValidationReport| {
+            report
+                .iter()
+                .map(|(field, status)| (field.to_string(), status.clone()))
+                .collect::<Vec<(String, FieldStatus)>>()
+        };
+        let input = std::fs::read_to_string("input.txt").unwrap();
+        let passports = parse_passports(&input).unwrap();
+        let eager: Vec<_> = passports.iter().map(|p| fields(&p.validate())).collect();
+        let streamed: Vec<_> = Passports::from_reader(input.as_bytes())
+            .reports()
+            .map(|report| fields(&report.unwrap()))
+            .collect();
+        assert_eq!(streamed, eager);
+        let validator = Validator::from(&ValidationConfig::strict());
+        let strict: Vec<_> = Passports::from_reader(input.as_bytes())
+            .reports_with(&validator)
+            .map(|report| fields(&report.unwrap()))
+            .collect();
+        let eager_strict: Vec<_> = passports.iter().map(|p| fields(&validator.validate(p))).collect();
+        assert_eq!(strict, eager_strict);
+    }
+
+    #[test]
+    fn records_differing_only_in_case_normalize_the_same() {
+        let lower = passport(&format!("{} cid:Ab", VALID));
+        let upper = passport(&format!("{} cid:Ab", VALID.replace("brn", "BRN").replace("abc", "ABC")));
+        assert_ne!(lower, upper);
+        assert_eq!(lower.normalized(), upper.normalized());
+        assert_eq!(upper.normalized().get("cid"), Some("Ab"));
+        let padded = Passport::builder().ecl(" Brn ").pid(" 000000001").build().normalized();
+        assert_eq!(padded, passport("ecl:brn pid:000000001"));
+    }
+
+    #[test]
+    fn duplicates_are_dropped_regardless_of_field_order() {
+        let mut passports = vec![
+            passport(VALID),
+            passport("byr:1980 pid:1"),
+            passport("pid:000000001 ecl:brn hcl:#123abc hgt:170cm eyr:2025 iyr:2015 byr:1980"),
+        ];
+        assert_eq!(dedup_passports(&mut passports), 1);
+        assert_eq!(passports, vec![passport(VALID), passport("byr:1980 pid:1")]);
+        assert_eq!(num_valid(&passports), 1);
+        assert_eq!(dedup_passports(&mut passports), 0);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_compressed_input_parses_to_the_same_counts() {
+        let input = std::fs::read("input.txt").unwrap();
+        let compressed = zstd::encode_all(&input[..], 0).unwrap();
+        let chars = CharReader::from_read(zstd::Decoder::new(&compressed[..]).unwrap());
+        let counts = chars.into_tokens().into_passports().count_by_status().unwrap();
+        assert_eq!((counts.total, counts.valid), (287, 127));
+
+        let path = std::env::temp_dir().join(format!("adv2020_4-{}.zst", std::process::id()));
+        std::fs::write(&path, &compressed).unwrap();
+        let from_file = open_char_reader_zst(&path).unwrap().into_tokens().into_passports().count();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file, 287);
+    }
+
+    #[test]
+    fn the_built_in_ranges_are_inclusive() {
+        let bounds = [
+            (BYR, "", 1920, 2002),
+            (IYR, "", 2010, 2020),
+            (EYR, "", 2020, 2030),
+            (HGT, "cm", 150, 193),
+            (HGT, "in", 59, 76),
+        ];
+        for (field, unit, from, to) in bounds {
+            let check = |amount: u32| validate_field(field, &format!("{}{}", amount, unit));
+            assert_eq!(check(from - 1), Some(false), "{} below {}{}", field, from, unit);
+            assert_eq!(check(from), Some(true), "{} at {}{}", field, from, unit);
+            assert_eq!(check(to), Some(true), "{} at {}{}", field, to, unit);
+            assert_eq!(check(to + 1), Some(false), "{} above {}{}", field, to, unit);
+        }
+    }
+
+    #[test]
+    fn a_missing_cid_follows_the_required_fields_policy() {
+        let north_pole = passport(VALID);
+        let mut config = ValidationConfig::default();
+        assert_eq!(config.required_fields_policy, RequiredFieldsPolicy::AllowMissingCid);
+        assert!(north_pole.contains_required_fields_with(&config));
+        assert!(north_pole.is_valid_with(&config));
+        config.required_fields_policy = RequiredFieldsPolicy::RequireCid;
+        assert!(!north_pole.contains_required_fields_with(&config));
+        assert_eq!(north_pole.status_with(&config), PassportStatus::Missing);
+        assert!(passport(&format!("{} cid:1", VALID)).contains_required_fields_with(&config));
+    }
+
+    #[test]
+    fn a_hash_map_converts_unless_a_key_is_empty() {
+        let mut map: HashMap<String, String> =
+            passport(VALID).iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let converted = Passport::try_from(map.clone()).unwrap();
+        assert_eq!(converted, passport(VALID));
+        assert!(converted.is_valid());
+        map.insert(String::new(), "1".to_string());
+        assert!(matches!(Passport::try_from(map), Err(Error::EmptyKey)));
+    }
+
+    #[test]
+    fn the_eye_color_histogram_counts_only_valid_passports() {
+        let with_ecl = |ecl: &str| passport(&VALID.replace("ecl:brn", &format!("ecl:{}", ecl)));
+        let passports = vec![
+            with_ecl("brn"),
+            with_ecl("blu"),
+            with_ecl("brn"),
+            with_ecl("xxx"),
+            passport(&VALID.replace("byr:1980", "byr:1900").replace("brn", "grn")),
+            passport("ecl:amb"),
+        ];
+        let mut counts: Vec<(String, usize)> = ecl_histogram(&passports).into_iter().collect();
+        counts.sort();
+        assert_eq!(counts, vec![("blu".to_string(), 1), ("brn".to_string(), 2)]);
+    }
+
+    #[test]
+    fn recovery_skips_a_stray_colon_and_keeps_reading() {
+        let input = format!("byr:1980 hgt:17:0cm ecl:brn\npid:1\n\n{}\n", VALID);
+        let mut passports = Passports::from_reader(input.as_bytes()).with_recovery(true);
+        let parsed = passports.by_ref().collect::<Result<Vec<Passport>>>().unwrap();
+        // The rest of the line after the stray colon goes, the next line of the record stays
+        assert_eq!(parsed, vec![passport("byr:1980 pid:1"), passport(VALID)]);
+        assert!(matches!(passports.errors(), [(1, Error::ColonInValue(..))]));
+        assert!(parse_passports(&input).is_err());
+
+        // A broken pair after a space-terminated pair and right before the blank line must not
+        // merge the records on either side of that line
+        for input in ["byr:1 hgt:1:2\n\npid:1\n", "byr:1 abc\n\npid:1\n"] {
+            let parsed = Passports::from_reader(input.as_bytes())
+                .with_recovery(true)
+                .collect::<Result<Vec<Passport>>>()
+                .unwrap();
+            assert_eq!(parsed, vec![passport("byr:1"), passport("pid:1")], "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn field_count_matches_the_fields_set() {
+        let built = Passport::builder().byr("1980").ecl("brn").field("xyz", "1").build();
+        assert_eq!(built.field_count(), 3);
+        assert!(!built.is_empty());
+        // Setting a field again replaces it rather than adding another
+        assert_eq!(Passport::builder().byr("1980").byr("1990").build().field_count(), 1);
+        let empty = Passport::builder().build();
+        assert_eq!(empty.field_count(), 0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn one_field_per_line_with_trailing_spaces() {
+        let input = "byr:1980 \niyr:2015\neyr:2025  \nhgt:170cm\t\nhcl:#123abc\necl:brn \npid:000000001 \n\
+                     \n\
+                     byr:1980 \n";
+        let passports = parse_passports(input).unwrap();
+        assert_eq!(passports, vec![passport(VALID), passport("byr:1980")]);
+    }
+
+    #[test]
+    fn strict_turns_on_every_tightened_rule() {
+        let strict = ValidationConfig::strict();
+        let with_cid = format!("{} cid:1", VALID);
+        assert_eq!(passport(&with_cid).status_with(&strict), PassportStatus::Valid);
+        // Each of these passes the default rules and fails one of the strict ones
+        for record in [VALID.to_string(), with_cid.replace("170cm", "0170cm")] {
+            assert_eq!(passport(&record).status(), PassportStatus::Valid, "{}", record);
+            assert_ne!(passport(&record).status_with(&strict), PassportStatus::Valid, "{}", record);
+        }
+        assert!(strict.check_year_order);
+    }
+
+    #[test]
+    fn to_map_round_trips_through_try_from() {
+        let original = passport(&format!("{} cid:12", VALID));
+        let map = original.to_map();
+        assert_eq!(map.len(), 8);
+        assert_eq!(map.get("hgt").map(String::as_str), Some("170cm"));
+        let back = Passport::try_from(map.into_iter().collect::<HashMap<String, String>>()).unwrap();
+        assert_eq!(back, original);
+        assert_eq!(back.to_map(), original.to_map());
+    }
+
+    #[test]
+    fn a_read_error_mid_stream_surfaces() {
+        // Returns its data and then fails rather than reporting the end of the input
+        struct Failing(io::Cursor<&'static [u8]>);
+
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.read(buf)? {
+                    0 => Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+                    read => Ok(read),
+                }
+            }
+        }
+
+        let data: &'static [u8] = b"byr:1980 pid:1\n\necl:brn";
+        let chars = CharReader::from_read(Failing(io::Cursor::new(data)));
+        let mut passports = chars.into_tokens().into_passports();
+        assert_eq!(passports.next().unwrap().unwrap(), passport("byr:1980 pid:1"));
+        match passports.next() {
+            Some(Err(Error::Io(e))) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let chars = CharReader::reversed_from_read(Failing(io::Cursor::new(data)));
+        assert!(matches!(chars.collect::<Result<String>>(), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn from_path_reads_the_records_of_a_file() {
+        assert_eq!(Passports::from_path("input.txt").unwrap().count(), 287);
+        let name = format!("adv2020_4-{}-from-path.txt", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, format!("{}\n\nbyr:1980\n", VALID)).unwrap();
+        let passports = Passports::from_path(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(passports, vec![passport(VALID), passport("byr:1980")]);
+        assert!(matches!(Passports::from_path("does_not_exist"), Err(Error::Open(..))));
+    }
+
+    #[test]
+    fn pairs_flatten_every_record() {
+        let input = "byr:1980 pid:1\n\n\necl:brn\nhgt:170cm\n";
+        let pairs = CharReader::new(input.as_bytes())
+            .into_tokens()
+            .pairs()
+            .collect::<Result<Vec<(String, String)>>>()
+            .unwrap();
+        let expected = [("byr", "1980"), ("pid", "1"), ("ecl", "brn"), ("hgt", "170cm")];
+        let expected: Vec<(String, String)> =
+            expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn generated_passports_follow_the_valid_ratio() {
+        let all = generate_passports(1000, 1.0, 100);
+        assert_eq!(all.len(), 1000);
+        assert!(all.iter().all(Passport::is_valid));
+        let none = generate_passports(1000, 0.0, 100);
+        assert!(none.iter().all(|passport| !passport.is_valid()));
+        let half = num_valid(&generate_passports(1000, 0.5, 100));
+        assert!((400..=600).contains(&half), "{}", half);
+        assert_eq!(generate_passports(50, 0.5, 7), generate_passports(50, 0.5, 7));
+    }
+
+    #[test]
+    fn a_blank_crlf_line_separates_records() {
+        let input = "byr:1980\r\n\r\npid:1\r\n";
+        let expected = vec![passport("byr:1980"), passport("pid:1")];
+        for chars in [CharReader::new(input.as_bytes()), CharReader::reversed(input.as_bytes())] {
+            let passports = chars.into_tokens().into_passports().collect::<Result<Vec<Passport>>>();
+            assert_eq!(passports.unwrap(), expected);
+        }
+    }
+}
@@ -1,391 +1,689 @@
-use std::collections::HashMap;
-use std::fmt::{self, Formatter};
-use std::fs::File;
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use std::collections::HashSet;
 use std::io::{self, BufRead};
-use std::path::Path;
-use std::{env, fs, result, str};
-
-use lazy_static::*;
-use regex::Regex;
-
-#[derive(Debug)]
-struct Error {
-    message: String,
+#[cfg(feature = "color")]
+use std::io::IsTerminal;
+use std::{env, process};
+
+use adv2020_4::{
+    dedup_passports, open_char_reader, CharReader, FieldStatus, IntoPassports, IntoTokens,
+    Passport, PassportStatus, RequiredFieldsPolicy, Result, ValidationConfig, Validator,
+};
+#[cfg(feature = "gzip")]
+use adv2020_4::open_char_reader_gz;
+#[cfg(feature = "zstd")]
+use adv2020_4::open_char_reader_zst;
+#[cfg(feature = "color")]
+use owo_colors::OwoColorize;
+
+enum Filter {
+    Valid,
+    Invalid,
+    Required,
 }
 
-impl Error {
-    fn new(message: String) -> Error {
-        Error { message }
+impl Filter {
+    fn matches(&self, passport: &Passport, validator: &Validator) -> bool {
+        let status = validator.status(passport);
+        match self {
+            Filter::Valid => status == PassportStatus::Valid,
+            Filter::Invalid => status != PassportStatus::Valid,
+            Filter::Required => status != PassportStatus::Missing,
+        }
     }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
-    }
+#[derive(Default)]
+enum Color {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
-macro_rules! error_from {
-    ($kind:path, $friendly_name:literal) => {
-        impl From<$kind> for Error {
-            fn from(e: $kind) -> Error {
-                Error::new(format!("{}: {}", $friendly_name, e))
+impl Color {
+    // Auto colors only a terminal, and never when NO_COLOR is set to anything but empty
+    #[cfg(feature = "color")]
+    fn enabled(&self) -> bool {
+        match self {
+            Color::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
             }
+            Color::Always => true,
+            Color::Never => false,
         }
-    };
-}
-
-error_from!(io::Error, "io error");
+    }
 
-fn reverse_string(s: String) -> String {
-    s.chars().rev().collect()
+    #[cfg(not(feature = "color"))]
+    fn enabled(&self) -> bool {
+        false
+    }
 }
 
-enum CharReaderState{
-    Reader(io::BufReader<fs::File>),
-    ReaderLine(io::BufReader<fs::File>, String),
-    Done,
+const EXIT_VALID: i32 = 0;
+const EXIT_NONE_VALID: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+// Names the input file when no file is given on the command line
+const INPUT_VAR: &str = "ADV2020_4_INPUT";
+
+const USAGE: &str = "\
+usage: adv2020_4 [options] [file...]
+
+Counts the passports in each file, where a file of - is stdin. When no file is
+given the file named by the ADV2020_4_INPUT environment variable is read, or
+stdin when it is not set.
+
+options:
+    -c, --count-only      print only the required fields and valid counts
+    --json                print the passports as json
+    --jsonl               print the passports as json, one object per line
+    --csv                 print the passports as csv, one row per passport
+    --stats               print how many passports failed on each field
+    --fields              print every key which was seen and how many passports have it
+    --ecl-stats           print how many valid passports have each eye color
+    --explain             print why each passport which is not valid failed
+    --color <when>        color the --explain output always, never or auto, which colors only a
+                          terminal and respects NO_COLOR. Needs the color feature
+    --filter <which>      print the valid, invalid or required passports
+    -q, --quiet           print nothing, only set the exit code
+    --limit <n>           read only the first n passports
+    --dedup               drop passports with the same fields as an earlier one
+    --rules <file>        validate with the rules in a json file instead of the built in rules
+    --strict              also reject heights with leading zeros, passports without a cid, and
+                          years which are out of order
+    --check <field=value> print whether a single value is valid for its field, and read no input
+    --print-rules         print the rules passports are validated with, and read no input
+    -h, --help            print this help
+
+exit codes:
+    0    at least one passport is valid
+    1    no passport is valid
+    2    an input could not be read or parsed, or the arguments were invalid";
+
+#[derive(Default)]
+struct Options {
+    filenames: Vec<String>,
+    count_only: bool,
+    json: bool,
+    jsonl: bool,
+    csv: bool,
+    stats: bool,
+    fields: bool,
+    ecl_stats: bool,
+    explain: bool,
+    color: Color,
+    filter: Option<Filter>,
+    quiet: bool,
+    rules: Option<String>,
+    limit: Option<usize>,
+    dedup: bool,
+    check: Option<(String, String)>,
+    strict: bool,
+    print_rules: bool,
 }
 
-struct CharReader {
-    state: Option<CharReaderState>
+fn usage_error(message: String) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(EXIT_ERROR);
 }
 
-impl CharReader {
-    fn new(reader: io::BufReader<fs::File>) -> CharReader {
-        CharReader{state: Some(CharReaderState::Reader(reader))}
+impl Options {
+    // Flags may appear anywhere, every argument which is not a flag is an input filename
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Options {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--count-only" | "-c" => options.count_only = true,
+                "--json" => options.json = true,
+                "--jsonl" => options.jsonl = true,
+                "--csv" => options.csv = true,
+                "--stats" => options.stats = true,
+                "--fields" => options.fields = true,
+                "--ecl-stats" => options.ecl_stats = true,
+                "--explain" => options.explain = true,
+                "--dedup" => options.dedup = true,
+                "--strict" => options.strict = true,
+                "--print-rules" => options.print_rules = true,
+                "--quiet" | "-q" => options.quiet = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(EXIT_VALID);
+                }
+                "--filter" => {
+                    options.filter = match args.next().as_deref() {
+                        Some("valid") => Some(Filter::Valid),
+                        Some("invalid") => Some(Filter::Invalid),
+                        Some("required") => Some(Filter::Required),
+                        _ => usage_error(
+                            "--filter must be one of valid, invalid or required".to_string(),
+                        ),
+                    }
+                }
+                "--color" => {
+                    options.color = match args.next().as_deref() {
+                        Some("auto") => Color::Auto,
+                        Some("always") => Color::Always,
+                        Some("never") => Color::Never,
+                        _ => usage_error("--color must be one of auto, always or never".to_string()),
+                    }
+                }
+                "--limit" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(limit) => options.limit = Some(limit),
+                    None => usage_error("--limit requires a number".to_string()),
+                },
+                "--check" => match args.next().as_deref().and_then(|c| c.split_once('=')) {
+                    Some((field, value)) => {
+                        options.check = Some((field.to_string(), value.to_string()))
+                    }
+                    None => usage_error("--check requires a field=value".to_string()),
+                },
+                "--rules" => match args.next() {
+                    Some(filename) => options.rules = Some(filename),
+                    None => usage_error("--rules requires a filename".to_string()),
+                },
+                flag if flag.starts_with('-') && flag != "-" => usage_error(format!("unknown option {}", flag)),
+                _ => options.filenames.push(arg),
+            }
+        }
+        if options.filenames.is_empty() {
+            match env::var_os(INPUT_VAR) {
+                Some(filename) if !filename.is_empty() => {
+                    options.filenames.push(filename.to_string_lossy().into_owned())
+                }
+                _ => (),
+            }
+        }
+        options
     }
 }
 
-impl Iterator for CharReader {
-    type Item = char;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        // Each call loops until a returnable state is reached.
-        // The outer match takes ownership of the state.
-        // Each branch MUST replace the state. To not do so is a logic error.
-        loop {
-            match self.state.take() {
-                // A branch has not replaced the state
-                None => panic!("none is not a valid state outside the next function"),
+#[cfg(feature = "serde")]
+fn annotate(passport: &Passport, validator: &Validator) -> serde_json::Value {
+    let status = validator.status(passport);
+    serde_json::json!({
+        "fields": passport,
+        "has_required_fields": status != PassportStatus::Missing,
+        "is_valid": status == PassportStatus::Valid,
+    })
+}
 
-                // The underlying reader reached its end state
-                Some(CharReaderState::Done) => {
-                    self.state = Some(CharReaderState::Done);
-                    return None
-                },
+#[cfg(feature = "serde")]
+fn print_json(passports: &[Passport], validator: &Validator) {
+    let annotated = passports
+        .iter()
+        .map(|p| annotate(p, validator))
+        .collect::<Vec<serde_json::Value>>();
+    match serde_json::to_string_pretty(&annotated) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("unable to serialize passports: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
 
-                // There is an underlying reader but no line buffer so
-                // try to read a line and on success update the state
-                Some(CharReaderState::Reader(mut reader)) => {
-                    let mut buf = String::new();
-                    match reader.read_line(&mut buf) {
-                        Ok(0) => self.state = Some(CharReaderState::Done),
-                        Ok(_) => self.state = Some(CharReaderState::ReaderLine(reader, reverse_string(buf))),
-                        Err(e) => {
-                            self.state = Some(CharReaderState::Done);
-                            println!("error reading from underlying bufreader: {}", e);
-                            // this is bad because it hides the error from downstream but this is not real code so...
-                        }
-                    }
-                },
+#[cfg(not(feature = "serde"))]
+fn print_json(_passports: &[Passport], _validator: &Validator) {
+    eprintln!("--json requires the serde feature");
+    process::exit(EXIT_ERROR);
+}
 
-                // There is a reader and a line buffer so try to pop a character from the
-                // line buffer and update the state, otherwise reset the state to having no current line buffer
-                Some(CharReaderState::ReaderLine(reader, mut line)) => {
-                    if let Some(c) = line.pop() {
-                        self.state = Some(CharReaderState::ReaderLine(reader, line));
-                        return Some(c)
-                    } else {
-                        self.state = Some(CharReaderState::Reader(reader))
-                    }
+// One compact json object per line, in the same form as the elements of the --json array. Each
+// passport is written as soon as it is parsed, so only one is held in memory at a time, or every
+// distinct one with --dedup. Returns the number of valid passports and whether any input failed.
+// An input which fails part way through keeps the lines which were already written
+#[cfg(feature = "serde")]
+fn stream_jsonl(filenames: &[String], options: &Options, validator: &Validator) -> (usize, bool) {
+    let mut remaining = options.limit.unwrap_or(usize::MAX);
+    let mut seen = HashSet::new();
+    let mut dropped = 0;
+    let mut num_valid = 0;
+    let mut failed = false;
+    for filename in filenames {
+        if remaining == 0 {
+            break;
+        }
+        let input = match open_input(filename) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("unable to read passports from {}: {}", input_name(filename), e);
+                failed = true;
+                continue;
+            }
+        };
+        for passport in input.take(remaining) {
+            let passport = match passport {
+                Ok(passport) => passport,
+                Err(e) => {
+                    eprintln!("unable to read passports from {}: {}", input_name(filename), e);
+                    failed = true;
+                    break;
                 }
+            };
+            remaining -= 1;
+            if options.dedup && !seen.insert(passport.clone()) {
+                dropped += 1;
+                continue;
+            }
+            if validator.status(&passport) == PassportStatus::Valid {
+                num_valid += 1;
             }
+            println!("{}", annotate(&passport, validator));
         }
     }
+    if options.dedup {
+        eprintln!("Dropped {} duplicate passports", dropped);
+    }
+    (num_valid, failed)
 }
 
-type Result<T> = result::Result<T, Error>;
-
-fn open_char_reader<P: AsRef<Path>>(filename: P) -> Result<CharReader> {
-    let file = File::open(filename)?;
-    Ok(CharReader::new(io::BufReader::new(file)))
+#[cfg(not(feature = "serde"))]
+fn stream_jsonl(_filenames: &[String], _options: &Options, _validator: &Validator) -> (usize, bool) {
+    eprintln!("--jsonl requires the serde feature");
+    process::exit(EXIT_ERROR);
 }
 
-struct Pair {
-    key: String,
-    value: String,
+// The columns of the csv output, ahead of the status columns
+#[cfg(feature = "csv")]
+const CSV_FIELDS: [&str; 8] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid", "cid"];
+
+#[cfg(feature = "csv")]
+fn write_csv<W: io::Write>(
+    writer: W,
+    passports: &[Passport],
+    validator: &Validator,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut header = CSV_FIELDS.to_vec();
+    header.extend(&["has_required", "is_valid"]);
+    writer.write_record(&header)?;
+    for passport in passports {
+        let status = validator.status(passport);
+        let mut row = CSV_FIELDS
+            .iter()
+            .map(|field| passport.get(field).unwrap_or(""))
+            .collect::<Vec<&str>>();
+        row.push(if status != PassportStatus::Missing { "true" } else { "false" });
+        row.push(if status == PassportStatus::Valid { "true" } else { "false" });
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
 }
 
-impl Pair {
-    fn new(key: String, value: String) -> Pair {
-        Pair { key, value }
+#[cfg(feature = "csv")]
+fn print_csv(passports: &[Passport], validator: &Validator) {
+    if let Err(e) = write_csv(io::stdout(), passports, validator) {
+        eprintln!("unable to write csv: {}", e);
+        process::exit(EXIT_ERROR);
     }
 }
 
-struct Tokens {
-    chars: CharReader,
+#[cfg(not(feature = "csv"))]
+fn print_csv(_passports: &[Passport], _validator: &Validator) {
+    eprintln!("--csv requires the csv feature");
+    process::exit(EXIT_ERROR);
 }
 
-impl Tokens {
-    fn new(chars: CharReader) -> Tokens {
-        Tokens { chars }
+// Missing fields are shown in red and invalid values in yellow
+#[cfg(feature = "color")]
+fn highlight(text: String, missing: bool, color: bool) -> String {
+    match (color, missing) {
+        (false, _) => text,
+        (true, true) => text.red().to_string(),
+        (true, false) => text.yellow().to_string(),
     }
+}
 
-    fn parse_pair(&mut self, initial: char) -> Result<Token> {
-        let mut key = format!("{}", initial);
-        let mut value = String::new();
-        loop {
-            let c = self.chars.next();
-            match c {
-                Some(':') => break,
-                Some('\n') => return Err(Error::new("newline in key".to_string())),
-                None => return Err(Error::new("end of file in key".to_string())),
-                Some(c) => key.push(c),
-            }
+#[cfg(not(feature = "color"))]
+fn highlight(text: String, _missing: bool, _color: bool) -> String {
+    text
+}
+
+// Describes why a passport is not valid, for example "line 42: missing [pid], invalid [hgt=190]".
+// A required field without a validator, such as a required cid, is still listed as missing
+fn explain(
+    passport: &Passport,
+    validator: &Validator,
+    config: &ValidationConfig,
+    color: bool,
+) -> String {
+    let report = validator.validate(passport);
+    let mut missing = Vec::new();
+    let mut invalid = Vec::new();
+    for (field, status) in report.iter() {
+        match status {
+            FieldStatus::Valid => (),
+            FieldStatus::Missing => missing.push(field.to_string()),
+            FieldStatus::Invalid(value) => invalid.push(format!("{}={}", field, value)),
         }
-        while let Some(c) = self.chars.next() {
-            match c {
-                '\n' | ' ' => break,
-                ':' => return Err(Error::new(": in value".to_string())),
-                _ => value.push(c),
-            }
+    }
+    let cid = match config.required_fields_policy {
+        RequiredFieldsPolicy::RequireCid => Some("cid".to_string()),
+        RequiredFieldsPolicy::AllowMissingCid => None,
+    };
+    for field in config.required.iter().chain(cid.as_ref()) {
+        if passport.get(field).is_none() && !missing.contains(field) {
+            missing.push(field.to_string());
         }
-        Ok(Token::Pair(Pair::new(key, value)))
     }
+
+    let mut reasons = Vec::new();
+    if !missing.is_empty() {
+        let missing = missing.into_iter().map(|field| highlight(field, true, color));
+        reasons.push(format!("missing [{}]", missing.collect::<Vec<_>>().join(", ")));
+    }
+    if !invalid.is_empty() {
+        let invalid = invalid.into_iter().map(|field| highlight(field, false, color));
+        reasons.push(format!("invalid [{}]", invalid.collect::<Vec<_>>().join(", ")));
+    }
+    let line = match passport.line() {
+        Some(line) => format!("line {}", line),
+        None => "unknown line".to_string(),
+    };
+    format!("{}: {}", line, reasons.join(", "))
 }
 
-enum Token {
-    Pair(Pair),
-    Break,
+// Uses par_count when the parallel feature is on
+#[cfg(not(feature = "parallel"))]
+fn count(passports: &[Passport], validator: &Validator) -> (usize, usize) {
+    validator.count(passports)
 }
 
-impl Iterator for Tokens {
-    type Item = Result<Token>;
+#[cfg(feature = "parallel")]
+fn count(passports: &[Passport], validator: &Validator) -> (usize, usize) {
+    validator.par_count(passports)
+}
 
-    fn next(&mut self) -> Option<Result<Token>> {
-        self.chars.next().map(|c| match c {
-            '\n' => Ok(Token::Break),
-            _ => self.parse_pair(c),
-        })
+fn print_stats(passports: &[Passport], validator: &Validator) {
+    let mut failures = validator.field_failures(passports).into_iter().collect::<Vec<_>>();
+    failures.sort();
+    for (field, count) in failures {
+        println!("{}: {} failures", field, count);
     }
 }
 
-trait IntoTokens {
-    fn into_tokens(self) -> Tokens;
+fn print_ecl_stats(passports: &[Passport], validator: &Validator) {
+    let colors = validator.ecl_histogram(passports);
+    for (color, count) in colors.into_iter().collect::<BTreeMap<_, _>>() {
+        println!("{}: {} passports", color, count);
+    }
 }
 
-
-impl IntoTokens for CharReader {
-    fn into_tokens(self) -> Tokens {
-        Tokens::new(self)
+// Keys are counted once per passport which has them, including keys which are not validated
+fn print_fields(passports: &[Passport]) {
+    let mut counts = BTreeMap::new();
+    for passport in passports {
+        for (key, _) in passport.iter() {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    for (key, count) in counts {
+        println!("{}: {} passports", key, count);
     }
 }
 
-struct Passport {
-    pairs: HashMap<String, String>,
+// The passports read from a single input
+struct Source {
+    name: String,
+    passports: Vec<Passport>,
 }
 
-const CID: &str = "cid";
-const BYR: &str = "byr";
-const IYR: &str = "iyr";
-const EYR: &str = "eyr";
-const HGT: &str = "hgt";
-const HCL: &str = "hcl";
-const ECL: &str = "ecl";
-const PID: &str = "pid";
+#[cfg(feature = "config")]
+fn load_rules(filename: &str) -> ValidationConfig {
+    match ValidationConfig::from_file(filename) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("unable to read rules from {}: {}", filename, e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
 
-lazy_static! {
-    static ref REQUIRED: Vec<String> = vec![
-        BYR.to_string(),
-        IYR.to_string(),
-        EYR.to_string(),
-        HGT.to_string(),
-        HCL.to_string(),
-        ECL.to_string(),
-        PID.to_string(),
-    ];
-    static ref HGT_REGEX: Regex =
-        Regex::new(r"^(?P<amount>\d+)(?P<unit>cm|in)$").expect("invalid height regex");
-    static ref HCL_REGEX: Regex = Regex::new(r"^#[a-f0-9]{6}$").expect("invalid hair color regex");
-    static ref ECL_REGEX: Regex =
-        Regex::new(r"^(?:amb|blu|brn|gry|grn|hzl|oth)$").expect("invalid eye color regex");
-    static ref PID_REGEX: Regex = Regex::new(r"^\d{9}$").expect("invalid passport id regex");
+#[cfg(not(feature = "config"))]
+fn load_rules(_filename: &str) -> ValidationConfig {
+    eprintln!("--rules requires the config feature");
+    process::exit(EXIT_ERROR);
 }
 
-impl Passport {
-    fn new(pairs: HashMap<String, String>) -> Passport {
-        Passport { pairs }
+// One setting per line, named as in a rules file, so a run can be recorded and reproduced
+fn print_rules(config: &ValidationConfig) {
+    let range = |(from, to): (u32, u32)| format!("{}-{}", from, to);
+    println!("required: {}", config.required.join(", "));
+    println!("byr: {}", range(config.byr));
+    println!("iyr: {}", range(config.iyr));
+    println!("eyr: {}", range(config.eyr));
+    println!("hgt_cm: {}", range(config.hgt_cm));
+    println!("hgt_in: {}", range(config.hgt_in));
+    println!("hgt_feet_and_inches: {}", config.hgt_feet_and_inches);
+    println!("hgt_strict_amount: {}", config.hgt_strict_amount);
+    println!("check_year_order: {}", config.check_year_order);
+    let policy = match config.required_fields_policy {
+        RequiredFieldsPolicy::AllowMissingCid => "allow_missing_cid",
+        RequiredFieldsPolicy::RequireCid => "require_cid",
+    };
+    println!("required_fields_policy: {}", policy);
+    let patterns = config.patterns.iter().collect::<BTreeMap<_, _>>();
+    for (field, pattern) in patterns {
+        println!("patterns.{}: {}", field, pattern);
     }
+}
 
-    fn contains_required_fields(&self) -> bool {
-        self.contains_passport_required_fields() || self.contains_north_pole_required_fields()
-    }
+// The passports of a single input, read lazily
+type Input = Box<dyn Iterator<Item = Result<Passport>>>;
 
-    fn contains_passport_required_fields(&self) -> bool {
-        self.pairs.len() == REQUIRED.len() + 1
-            && self.contains_min_fields()
-            && self.contains_cid_field()
-    }
+fn passports<R: BufRead + 'static>(chars: CharReader<R>) -> Input {
+    Box::new(chars.into_tokens().into_passports())
+}
 
-    fn contains_north_pole_required_fields(&self) -> bool {
-        self.pairs.len() == REQUIRED.len() && self.contains_min_fields()
+// A filename of - reads stdin. A file actually named - can be given as ./-
+fn open_input(filename: &str) -> Result<Input> {
+    if filename == "-" {
+        return Ok(passports(CharReader::new(io::stdin().lock())));
     }
-
-    fn contains_min_fields(&self) -> bool {
-        REQUIRED.iter().all(|key| self.pairs.contains_key(key))
+    #[cfg(feature = "gzip")]
+    {
+        if filename.ends_with(".gz") {
+            return Ok(passports(open_char_reader_gz(filename)?));
+        }
     }
-
-    fn contains_cid_field(&self) -> bool {
-        self.pairs.contains_key(CID)
+    #[cfg(feature = "zstd")]
+    {
+        if filename.ends_with(".zst") {
+            return Ok(passports(open_char_reader_zst(filename)?));
+        }
     }
+    Ok(passports(open_char_reader(filename)?))
+}
 
-    fn is_valid(&self) -> bool {
-        self.is_valid_byr()
-            && self.is_valid_iyr()
-            && self.is_valid_eyr()
-            && self.is_valid_hgt()
-            && self.is_valid_hcl()
-            && self.is_valid_ecl()
-            && self.is_valid_pid()
+fn input_name(filename: &str) -> &str {
+    if filename == "-" {
+        "stdin"
+    } else {
+        filename
     }
+}
 
-    fn is_valid_byr(&self) -> bool {
-        self.value_in_valid_u32_range(BYR, 1920, 2002)
+// Reads every input, stopping once limit passports have been read so that the remaining inputs
+// are not read at all. Returns the inputs which were read and whether any failed
+fn read_inputs(filenames: &[String], limit: Option<usize>) -> (Vec<Source>, bool) {
+    let mut sources = Vec::new();
+    let mut failed = false;
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    for filename in filenames {
+        if remaining == 0 {
+            break;
+        }
+        let name = input_name(filename);
+        let passports = open_input(filename)
+            .and_then(|input| input.take(remaining).collect::<Result<Vec<Passport>>>());
+        match passports {
+            Ok(passports) => {
+                remaining -= passports.len();
+                sources.push(Source {
+                    name: name.to_string(),
+                    passports,
+                });
+            }
+            Err(e) => {
+                eprintln!("unable to read passports from {}: {}", name, e);
+                failed = true;
+            }
+        }
     }
+    (sources, failed)
+}
 
-    fn is_valid_iyr(&self) -> bool {
-        self.value_in_valid_u32_range(IYR, 2010, 2020)
+// Prints the requested output and returns the number of valid passports
+fn report(
+    sources: Vec<Source>,
+    options: &Options,
+    validator: &Validator,
+    config: &ValidationConfig,
+) -> usize {
+    let listing = options.json || options.csv || options.explain || options.filter.is_some();
+
+    // Each file gets its own line ahead of the totals when reading more than one
+    if sources.len() > 1 && !options.quiet && !options.count_only && !listing {
+        for source in &sources {
+            let (num_required, num_valid) = count(&source.passports, validator);
+            println!(
+                "{}: {} passports, {} with the required fields, {} valid",
+                source.name,
+                source.passports.len(),
+                num_required,
+                num_valid
+            );
+        }
     }
 
-    fn is_valid_eyr(&self) -> bool {
-        self.value_in_valid_u32_range(EYR, 2020, 2030)
-    }
+    let mut passports = sources
+        .into_iter()
+        .flat_map(|source| source.passports)
+        .collect::<Vec<Passport>>();
 
-    fn is_valid_hgt(&self) -> bool {
-        self.pairs
-            .get(HGT)
-            .and_then(|value| HGT_REGEX.captures(value))
-            .and_then(|captures| {
-                captures
-                    .name("amount")
-                    .map(|height| (captures, height.as_str().to_string()))
-            })
-            .and_then(|(captures, height)| {
-                captures
-                    .name("unit")
-                    .map(|unit| (height, unit.as_str().to_string()))
-            })
-            .and_then(|(height, unit)| {
-                height.parse::<u32>().ok().map(|h| {
-                    (unit == "cm" && h >= 150 && h <= 193) || (unit == "in" && h >= 59 && h <= 76)
-                })
-            })
-            .unwrap_or(false)
+    // Duplicates are dropped across all of the inputs, so only from the totals
+    if options.dedup {
+        let dropped = dedup_passports(&mut passports);
+        if !options.quiet {
+            eprintln!("Dropped {} duplicate passports", dropped);
+        }
     }
 
-    fn is_valid_hcl(&self) -> bool {
-        is_valid_match(self.pairs.get(HCL), &HCL_REGEX)
-    }
+    let (num_required, num_valid) = count(&passports, validator);
 
-    fn is_valid_ecl(&self) -> bool {
-        is_valid_match(self.pairs.get(ECL), &ECL_REGEX)
+    if options.quiet {
+        return num_valid;
     }
 
-    fn is_valid_pid(&self) -> bool {
-        is_valid_match(self.pairs.get(PID), &PID_REGEX)
+    if options.json {
+        print_json(&passports, validator);
+        return num_valid;
     }
 
-    fn value_in_valid_u32_range(&self, key: &str, from: u32, to: u32) -> bool {
-        self.pairs
-            .get(key)
-            .and_then(|value| value.parse::<u32>().ok())
-            .map_or(false, |y| y >= from && y <= to)
+    if options.csv {
+        print_csv(&passports, validator);
+        return num_valid;
     }
 
-    fn _print(&self) {
-        let out = self
-            .pairs
+    if options.explain {
+        let color = options.color.enabled();
+        passports
             .iter()
-            .map(|(k, v)| format!("{}:{}", k, v))
-            .collect::<Vec<String>>()
-            .join(" ");
-        println!("{}", out);
+            .filter(|p| validator.status(p) != PassportStatus::Valid)
+            .for_each(|p| println!("{}", explain(p, validator, config, color)));
+        return num_valid;
     }
-}
 
-fn is_valid_match(value: Option<&String>, regex: &Regex) -> bool {
-    value.map(|v| regex.is_match(v)).unwrap_or(false)
-}
+    if let Some(filter) = &options.filter {
+        passports
+            .iter()
+            .filter(|p| filter.matches(p, validator))
+            .for_each(|p| println!("{}", p));
+        return num_valid;
+    }
 
-struct Passports {
-    tokens: Tokens,
-}
+    if options.count_only {
+        println!("{}", num_required);
+        println!("{}", num_valid);
+    } else {
+        println!("There are {} passports", passports.len());
+        println!(
+            "There are {} passports with the required fields",
+            num_required
+        );
+        println!("There are {} valid passports", num_valid);
+    }
 
-impl Passports {
-    fn new(tokens: Tokens) -> Passports {
-        Passports { tokens }
+    if options.stats {
+        print_stats(&passports, validator);
     }
-}
 
-impl Iterator for Passports {
-    type Item = Passport;
+    if options.fields {
+        print_fields(&passports);
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut pairs: HashMap<String, String> = HashMap::new();
-        loop {
-            match self.tokens.next() {
-                Some(Ok(Token::Pair(p))) => {
-                    pairs.insert(p.key, p.value);
-                }
-                Some(Ok(Token::Break)) | None if !pairs.is_empty() => {
-                    return Some(Passport::new(pairs));
-                }
-                // ignore extra line breaks
-                Some(Ok(Token::Break)) => (),
-                Some(Err(e)) => panic!("card error: {}", e),
-                None => break,
-            }
-        }
-        None
+    if options.ecl_stats {
+        print_ecl_stats(&passports, validator);
     }
-}
 
-trait IntoPassports {
-    fn into_passports(self) -> Passports;
+    num_valid
 }
 
-impl IntoPassports for Tokens {
-    fn into_passports(self) -> Passports {
-        Passports::new(self)
+// A file which cannot be read or parsed is reported and skipped so that the remaining files are
+// still counted. The process still exits with the error status if any input failed
+fn main() {
+    let options = Options::parse(env::args().skip(1));
+    let mut config = match &options.rules {
+        Some(filename) => load_rules(filename),
+        None => ValidationConfig::default(),
+    };
+    if options.strict {
+        config = config.into_strict();
     }
-}
+    let validator = Validator::from(&config);
 
-fn main() -> Result<()> {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() > 1 {
-        let passports: Vec<Passport> = open_char_reader(&args[1])?
-            .into_tokens()
-            .into_passports()
-            .collect();
+    if options.print_rules {
+        print_rules(&config);
+        process::exit(EXIT_VALID);
+    }
 
-        let num_required = passports
-            .iter()
-            .filter(|p| p.contains_required_fields())
-            .count();
+    if let Some((field, value)) = &options.check {
+        match validator.validate_field(field, value) {
+            Some(true) => {
+                println!("valid");
+                process::exit(EXIT_VALID);
+            }
+            Some(false) => {
+                println!("invalid");
+                process::exit(EXIT_NONE_VALID);
+            }
+            None => usage_error(format!("--check has no rules for the field {}", field)),
+        }
+    }
 
-        let num_valid = passports
-            .iter()
-            .filter(|p| p.contains_required_fields() && p.is_valid())
-            .count();
+    let stdin = ["-".to_string()];
+    let filenames = if options.filenames.is_empty() {
+        &stdin[..]
+    } else {
+        &options.filenames[..]
+    };
+    // --jsonl writes passports as they are read, unless another mode takes precedence
+    let (num_valid, failed) = if options.jsonl && !options.quiet && !options.json {
+        stream_jsonl(filenames, &options, &validator)
+    } else {
+        let (sources, failed) = read_inputs(filenames, options.limit);
+        (report(sources, &options, &validator, &config), failed)
+    };
 
-        println!(
-            "There are {} passports with the required fields",
-            num_required
-        );
-        println!("There are {} valid passports", num_valid);
-        Ok(())
+    if failed {
+        process::exit(EXIT_ERROR);
+    } else if num_valid == 0 {
+        process::exit(EXIT_NONE_VALID);
     } else {
-        panic!("input filename is required");
+        process::exit(EXIT_VALID);
     }
 }
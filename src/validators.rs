@@ -0,0 +1,102 @@
+// Standalone checks of single values. The built in field validators are built on these, and the
+// functions named after a field check a value against the default rules for that field
+use crate::{ECL_REGEX, HCL_REGEX, HGT_FEET_REGEX, HGT_REGEX, HGT_STRICT_REGEX, PID_REGEX};
+
+// The default inclusive ranges
+pub const BYR_RANGE: (u32, u32) = (1920, 2002);
+pub const IYR_RANGE: (u32, u32) = (2010, 2020);
+pub const EYR_RANGE: (u32, u32) = (2020, 2030);
+pub const HGT_CM_RANGE: (u32, u32) = (150, 193);
+pub const HGT_IN_RANGE: (u32, u32) = (59, 76);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeightUnit {
+    Cm,
+    In,
+}
+
+pub fn in_range(n: u32, (from, to): (u32, u32)) -> bool {
+    n >= from && n <= to
+}
+
+// Years must be exactly four ascii digits. This is checked up front rather than left to
+// u32::from_str, which also accepts a leading +
+pub fn parse_year(value: &str) -> Option<u32> {
+    if value.len() == 4 && value.bytes().all(|b| b.is_ascii_digit()) {
+        value.parse().ok()
+    } else {
+        None
+    }
+}
+
+pub fn year_in_range(value: &str, range: (u32, u32)) -> bool {
+    parse_year(value).is_some_and(|year| in_range(year, range))
+}
+
+// The amount and unit of a height such as 180cm or 70in. When strict, amounts with leading
+// zeros, such as 0150cm, are rejected
+pub fn parse_height(value: &str, strict: bool) -> Option<(u32, HeightUnit)> {
+    let regex = if strict { &*HGT_STRICT_REGEX } else { &*HGT_REGEX };
+    let captures = regex.captures(value)?;
+    let amount = captures.name("amount")?.as_str().parse().ok()?;
+    let unit = match captures.name("unit")?.as_str() {
+        "cm" => HeightUnit::Cm,
+        _ => HeightUnit::In,
+    };
+    Some((amount, unit))
+}
+
+// A height such as 5ft10in or 6ft in inches. Twelve or more inches is rejected
+pub fn parse_feet_and_inches(value: &str) -> Option<u32> {
+    let captures = HGT_FEET_REGEX.captures(value)?;
+    let feet = captures.name("feet")?.as_str().parse::<u32>().ok()?;
+    let inches = match captures.name("inches") {
+        Some(inches) => inches.as_str().parse::<u32>().ok()?,
+        None => 0,
+    };
+    if inches >= 12 {
+        return None;
+    }
+    feet.checked_mul(12)?.checked_add(inches)
+}
+
+// Checks a parsed height against the range for its unit
+pub fn height_in_range(
+    (amount, unit): (u32, HeightUnit),
+    cm: (u32, u32),
+    inches: (u32, u32),
+) -> bool {
+    match unit {
+        HeightUnit::Cm => in_range(amount, cm),
+        HeightUnit::In => in_range(amount, inches),
+    }
+}
+
+pub fn byr(value: &str) -> bool {
+    year_in_range(value, BYR_RANGE)
+}
+
+pub fn iyr(value: &str) -> bool {
+    year_in_range(value, IYR_RANGE)
+}
+
+pub fn eyr(value: &str) -> bool {
+    year_in_range(value, EYR_RANGE)
+}
+
+pub fn hgt(value: &str) -> bool {
+    parse_height(value, false)
+        .is_some_and(|height| height_in_range(height, HGT_CM_RANGE, HGT_IN_RANGE))
+}
+
+pub fn hcl(value: &str) -> bool {
+    HCL_REGEX.is_match(value)
+}
+
+pub fn ecl(value: &str) -> bool {
+    ECL_REGEX.is_match(value)
+}
+
+pub fn pid(value: &str) -> bool {
+    PID_REGEX.is_match(value)
+}
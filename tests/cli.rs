@@ -0,0 +1,331 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+use std::{env, fs};
+
+// Runs the binary over the given stdin, without the input environment variable or NO_COLOR
+fn run(args: &[&str], stdin: &str) -> Output {
+    run_with_input_var(args, stdin, None)
+}
+
+// As run, but with ADV2020_4_INPUT set when an input is given
+fn run_with_input_var(args: &[&str], stdin: &str, input: Option<&PathBuf>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_adv2020_4"));
+    command.env_remove("ADV2020_4_INPUT");
+    if let Some(input) = input {
+        command.env("ADV2020_4_INPUT", input);
+    }
+    let mut child = command
+        .args(args)
+        .env_remove("NO_COLOR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("unable to run adv2020_4");
+    // The binary may stop reading early, as with --limit, and close its end of the pipe
+    if let Err(e) = child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe, "{}", e);
+    }
+    child.wait_with_output().unwrap()
+}
+
+// Writes a fixture to the temp directory. Names must be unique across the tests, which share it
+fn fixture(name: &str, contents: &[u8]) -> PathBuf {
+    let path = env::temp_dir().join(format!("adv2020_4-{}-{}", process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+const VALID: &str = "byr:1980 iyr:2015 eyr:2025 hgt:170cm hcl:#123abc ecl:brn pid:000000001";
+
+const EXPLAIN_INPUT: &str = "byr:1980 iyr:2015 eyr:2025 hgt:150cm hcl:#123abc ecl:brn\n\n\
+                             byr:1980 iyr:2015 eyr:2025 hgt:190 hcl:#123abc ecl:brn pid:000000001\n";
+
+#[test]
+fn explain_without_color_is_plain() {
+    let expected = "line 1: missing [pid]\nline 3: invalid [hgt=190]\n";
+    assert_eq!(stdout(&run(&["--explain"], EXPLAIN_INPUT)), expected);
+    assert_eq!(stdout(&run(&["--explain", "--color", "never"], EXPLAIN_INPUT)), expected);
+}
+
+#[cfg(feature = "color")]
+#[test]
+fn explain_with_color_forced_on_highlights_fields() {
+    let out = stdout(&run(&["--explain", "--color", "always"], EXPLAIN_INPUT));
+    assert!(out.contains("\u{1b}[31mpid\u{1b}[39m"), "{:?}", out);
+    assert!(out.contains("\u{1b}[33mhgt=190\u{1b}[39m"), "{:?}", out);
+}
+
+#[test]
+fn check_validates_a_single_field() {
+    let check = |arg: &str| {
+        let output = run(&["--check", arg], "");
+        (stdout(&output), output.status.code())
+    };
+    assert_eq!(check("hgt=180cm"), ("valid\n".to_string(), Some(0)));
+    assert_eq!(check("hgt=194cm"), ("invalid\n".to_string(), Some(1)));
+    assert_eq!(check("hgt=190"), ("invalid\n".to_string(), Some(1)));
+    assert_eq!(check("xyz=whatever"), (String::new(), Some(2)));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn jsonl_writes_one_object_per_passport() {
+    let input = "byr:1980\n\npid:1 hgt:190\n\necl:brn\n";
+    let out = stdout(&run(&["--jsonl"], input));
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3, "{:?}", out);
+    for line in lines {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(value.is_object(), "{}", line);
+    }
+}
+
+#[test]
+fn limit_counts_only_the_first_passports() {
+    let input = format!("{}\n\nbyr:1980\n\n{}\n", VALID, VALID);
+    let counts = |total: usize, required: usize, valid: usize| {
+        format!(
+            "There are {} passports\nThere are {} passports with the required fields\n\
+             There are {} valid passports\n",
+            total, required, valid
+        )
+    };
+    assert_eq!(stdout(&run(&["--limit", "0"], &input)), counts(0, 0, 0));
+    assert_eq!(stdout(&run(&["--limit", "2"], &input)), counts(2, 1, 1));
+    assert_eq!(stdout(&run(&["--limit", "10"], &input)), counts(3, 2, 2));
+}
+
+#[test]
+fn reads_stdin_without_a_filename() {
+    let input = format!("{}\n\nbyr:1980\n", VALID);
+    let output = run(&[], &input);
+    assert_eq!(
+        stdout(&output),
+        "There are 2 passports\nThere are 1 passports with the required fields\n\
+         There are 1 valid passports\n"
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&run(&["-"], &input)), stdout(&output));
+}
+
+#[test]
+fn stats_prints_the_failures_for_each_field() {
+    let input = format!(
+        "{}\n\n{}\n",
+        VALID,
+        VALID.replace("byr:1980", "byr:2003").replace("hgt:170cm", "hgt:170")
+    );
+    let out = stdout(&run(&["--stats"], &input));
+    assert!(out.ends_with("byr: 1 failures\nhgt: 1 failures\n"), "{:?}", out);
+}
+
+#[test]
+fn multiple_files_are_counted_separately_and_together() {
+    let first = fixture("multi-first.txt", format!("{}\n\nbyr:1\n", VALID).as_bytes());
+    let second = fixture("multi-second.txt", b"byr:1980\n");
+    let missing = env::temp_dir().join(format!("adv2020_4-{}-missing.txt", process::id()));
+    let name = |path: &PathBuf| path.to_str().unwrap().to_string();
+
+    let output = run(&[&name(&first), &name(&second)], "");
+    let expected = format!(
+        "{}: 2 passports, 1 with the required fields, 1 valid\n\
+         {}: 1 passports, 0 with the required fields, 0 valid\n\
+         There are 3 passports\nThere are 1 passports with the required fields\n\
+         There are 1 valid passports\n",
+        name(&first),
+        name(&second)
+    );
+    assert_eq!(stdout(&output), expected);
+    assert_eq!(output.status.code(), Some(0));
+
+    // A file which cannot be read is reported, the rest are still counted and the exit code is 2
+    let output = run(&[&name(&first), &name(&missing)], "");
+    let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+    assert!(stderr.contains(&name(&missing)), "{}", stderr);
+    assert!(stdout(&output).starts_with("There are 2 passports\n"), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(2));
+
+    fs::remove_file(first).unwrap();
+    fs::remove_file(second).unwrap();
+}
+
+#[test]
+fn exit_codes_follow_the_valid_passports() {
+    let all_invalid = format!("{}\n\nbyr:1980\n", VALID.replace("byr:1980", "byr:1900"));
+    let output = run(&[], &all_invalid);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).ends_with("There are 0 valid passports\n"));
+
+    let output = run(&["--quiet"], &all_invalid);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(run(&["--quiet"], VALID).status.code(), Some(0));
+    assert_eq!(run(&["--quiet"], "byr:19:80\n").status.code(), Some(2));
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn csv_writes_a_row_for_each_passport() {
+    let input = format!("{}\n\ncid:1,2 byr:1\n", VALID);
+    let expected = "byr,iyr,eyr,hgt,hcl,ecl,pid,cid,has_required,is_valid\n\
+                    1980,2015,2025,170cm,#123abc,brn,000000001,,true,true\n\
+                    1,,,,,,,\"1,2\",false,false\n";
+    assert_eq!(stdout(&run(&["--csv"], &input)), expected);
+}
+
+#[test]
+fn explain_is_silent_for_valid_passports() {
+    let input = format!("{}\n\n{}", VALID, EXPLAIN_INPUT);
+    let out = stdout(&run(&["--explain"], &input));
+    assert_eq!(out, "line 3: missing [pid]\nline 5: invalid [hgt=190]\n");
+    assert_eq!(stdout(&run(&["--explain"], VALID)), "");
+}
+
+#[test]
+fn the_input_variable_is_read_without_a_filename() {
+    let from_var = fixture("env-var.txt", format!("{}\n\nbyr:1980\n", VALID).as_bytes());
+    let from_arg = fixture("env-arg.txt", b"byr:1980\n");
+    let total = |output: &Output| stdout(output).lines().next().unwrap().to_string();
+
+    let output = run_with_input_var(&[], "pid:1\n\npid:2\n\npid:3\n", Some(&from_var));
+    assert_eq!(total(&output), "There are 2 passports");
+    // An explicit filename, including - for stdin, takes precedence over the variable
+    let output = run_with_input_var(&[from_arg.to_str().unwrap()], "", Some(&from_var));
+    assert_eq!(total(&output), "There are 1 passports");
+    let output = run_with_input_var(&["-"], "pid:1\n\npid:2\n\npid:3\n", Some(&from_var));
+    assert_eq!(total(&output), "There are 3 passports");
+
+    fs::remove_file(from_var).unwrap();
+    fs::remove_file(from_arg).unwrap();
+}
+
+#[test]
+fn a_dash_reads_stdin_and_a_path_reads_a_file_named_dash() {
+    let output = run(&["-"], &format!("{}\n\nbyr:1980\n", VALID));
+    assert!(stdout(&output).starts_with("There are 2 passports\n"), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(0));
+
+    let dir = env::temp_dir().join(format!("adv2020_4-{}-dash", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("-");
+    fs::write(&file, "byr:1980\n").unwrap();
+    let output = run(&[file.to_str().unwrap()], &format!("{}\n", VALID));
+    assert!(stdout(&output).starts_with("There are 1 passports\n"), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn blank_only_input_prints_zero_counts() {
+    let output = run(&[], "\n\n \n\n");
+    assert_eq!(
+        stdout(&output),
+        "There are 0 passports\nThere are 0 passports with the required fields\n\
+         There are 0 valid passports\n"
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn fields_lists_every_key_with_its_count() {
+    let input = format!("byr:1980 xyz:1\n\nxyz:2 pid:1\n\n{}\n", VALID);
+    let out = stdout(&run(&["--fields"], &input));
+    let fields: Vec<&str> = out.lines().skip(3).collect();
+    assert_eq!(
+        fields,
+        vec![
+            "byr: 2 passports",
+            "ecl: 1 passports",
+            "eyr: 1 passports",
+            "hcl: 1 passports",
+            "hgt: 1 passports",
+            "iyr: 1 passports",
+            "pid: 2 passports",
+            "xyz: 2 passports",
+        ]
+    );
+}
+
+#[test]
+fn dedup_counts_a_repeated_record_once() {
+    let reordered = "pid:000000001 ecl:brn hcl:#123abc hgt:170cm eyr:2025 iyr:2015 byr:1980";
+    let input = format!("{}\n\nbyr:1\n\n{}\n", VALID, reordered);
+    let output = run(&["--dedup"], &input);
+    assert_eq!(
+        stdout(&output),
+        "There are 2 passports\nThere are 1 passports with the required fields\n\
+         There are 1 valid passports\n"
+    );
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Dropped 1 duplicate passports\n");
+}
+
+#[test]
+fn ecl_stats_prints_the_eye_colors_of_valid_passports() {
+    let input = format!(
+        "{}\n\n{}\n\n{}\n\necl:amb\n",
+        VALID,
+        VALID.replace("brn", "blu"),
+        VALID
+    );
+    let out = stdout(&run(&["--ecl-stats"], &input));
+    assert!(out.ends_with("valid passports\nblu: 1 passports\nbrn: 2 passports\n"), "{}", out);
+}
+
+#[test]
+fn strict_rejects_a_passport_the_default_rules_accept() {
+    let input = format!("{} cid:1\n", VALID.replace("170cm", "0170cm"));
+    assert_eq!(run(&["--quiet"], &input).status.code(), Some(0));
+    assert_eq!(run(&["--quiet", "--strict"], &input).status.code(), Some(1));
+    assert_eq!(run(&["--quiet", "--strict"], &format!("{} cid:1\n", VALID)).status.code(), Some(0));
+}
+
+#[test]
+fn print_rules_shows_the_default_rules() {
+    let out = stdout(&run(&["--print-rules"], ""));
+    assert!(out.contains("byr: 1920-2002\n"), "{}", out);
+    assert!(out.contains("required_fields_policy: allow_missing_cid\n"), "{}", out);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn print_rules_reflects_a_rules_file() {
+    let rules = fixture(
+        "print-rules.json",
+        br#"{"byr": [1900, 2000], "hgt_strict_amount": true, "required_fields_policy": "require_cid"}"#,
+    );
+    let output = run(&["--rules", rules.to_str().unwrap(), "--print-rules"], "");
+    fs::remove_file(rules).unwrap();
+    let out = stdout(&output);
+    assert!(out.contains("byr: 1900-2000\n"), "{}", out);
+    assert!(out.contains("iyr: 2010-2020\n"), "{}", out);
+    assert!(out.contains("hgt_strict_amount: true\n"), "{}", out);
+    assert!(out.contains("check_year_order: false\n"), "{}", out);
+    assert!(out.contains("required_fields_policy: require_cid\n"), "{}", out);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn filter_prints_the_chosen_subset() {
+    let invalid = VALID.replace("hgt:170cm", "hgt:170");
+    let input = format!("{}\n\nbyr:1980\n\n{}\n", VALID, invalid);
+    let filter = |which: &str| {
+        let output = run(&["--filter", which], &input);
+        (stdout(&output), output.status.code())
+    };
+    assert_eq!(filter("valid"), (format!("{}\n", VALID), Some(0)));
+    assert_eq!(filter("invalid"), (format!("byr:1980\n{}\n", invalid), Some(0)));
+    assert_eq!(filter("required"), (format!("{}\n{}\n", VALID, invalid), Some(0)));
+    let output = run(&["--filter", "bogus"], &input);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+
+    // -c prints only the required fields and valid counts
+    assert_eq!(stdout(&run(&["-c"], &input)), "2\n1\n");
+    assert_eq!(stdout(&run(&["--count-only"], &input)), "2\n1\n");
+}